@@ -1,300 +1,3951 @@
 use {
-    chrono::naive::NaiveDate,
+    chrono::{naive::NaiveDate, Datelike},
+    crate::{error::BudgetError, money::{CurrencyFormat, Money}, parser::parse_date},
     self::{AccountType::*, Action::*, Inflow::*},
-    std::fmt,
+    serde::{Deserialize, Serialize},
+    std::{convert::TryFrom, fmt, fs::File, io::{self, BufRead, BufReader, Read, Write}, iter::once, mem, path::Path, str::FromStr},
 };
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Account {
     name: String,
-    data: AccountType
+    data: AccountType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created: Option<NaiveDate>,
+    // The date of the last action that changed this leaf's balance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_activity: Option<NaiveDate>,
+    // Frozen accounts can't have money taken out of them, like an emergency fund
+    #[serde(default, skip_serializing_if = "is_false")]
+    frozen: bool,
+    // Archived accounts are left out of their parent's totals, deposits, and display, but kept for their history
+    #[serde(default, skip_serializing_if = "is_false")]
+    archived: bool
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BranchEntry {
     account: Account,
     inflow: Inflow,
+    // Children with a higher priority are funded as fully as possible before lower ones get anything
+    #[serde(default)]
+    priority: i32,
 }
 
-#[derive(Clone, Debug)]
+/// A depth-first traversal of an account and all of its descendants
+pub struct Iter<'a> {
+    stack: Vec<&'a Account>,
+    archived: bool
+}
+
+/// Callbacks for walking a tree in the same order it's displayed, given how deep each account is and the inflow it
+/// takes from its parent, which the account the walk starts from doesn't have
+///
+/// Archived accounts are skipped, as they are when displaying.
+pub trait Visitor {
+    fn enter_branch(&mut self, _depth: u32, _account: &Account, _inflow: Option<&Inflow>) {}
+    fn visit_leaf(&mut self, _depth: u32, _account: &Account, _inflow: Option<&Inflow>) {}
+    fn exit_branch(&mut self, _depth: u32, _account: &Account) {}
+}
+
+/// Declares a tree of accounts in code, checking each child the same way the New action would
+///
+/// The first problem found is kept and reported by `build`, and anything added after it is ignored.
+pub struct AccountBuilder {
+    account: Account,
+    error: Option<BudgetError>
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum AccountType {
-    Leaf { balance: f64, max: f64 },
-    Branch { children: Vec<BranchEntry> }
+    Leaf {
+        balance: Money,
+        max: Money,
+        // Whether withdrawals may take the balance below zero, e.g. for credit cards
+        #[serde(default)]
+        allow_negative: bool,
+        #[serde(default)]
+        rollover: Rollover,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        goal: Option<Goal>,
+        // An ISO 4217 code like EUR, for a leaf held in something other than the budget's own currency
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<String>
+    },
+    Branch {
+        children: Vec<BranchEntry>,
+        // The child or descendant that deposits left over after inflows go to, instead of being split up
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        overflow: Option<String>,
+        // The smallest amount deposits are split into, the same for the whole tree
+        #[serde(default = "cent", skip_serializing_if = "is_cent")]
+        unit: Money
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "amount")]
 pub enum Inflow  {
-    Fixed(f64),
-    Flex(f64)
+    Fixed(Money),
+    Flex(f64),
+    /// A percentage of each deposit into the parent, taken before any fixed or flex inflows
+    Percent(f64),
+    /// Whatever it takes to bring the account up to its max, taken alongside the fixed inflows
+    FillToMax
+}
+
+/// A balance a leaf should reach by some date, like a sinking fund for a vacation
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Goal {
+    pub target: Money,
+    pub by: NaiveDate
+}
+
+/// What happens to a leaf's balance at the start of a new month
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Rollover {
+    /// Carry the balance over, so sinking funds accumulate
+    #[default]
+    Keep,
+    /// Start from zero, as for spending categories
+    Reset
+}
+
+/// How one leaf differs between two snapshots of a tree, identified by its path like root/Living/Food
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccountDiff {
+    Changed { path: String, before: Money, after: Money },
+    Added { path: String, balance: Money },
+    Removed { path: String, balance: Money }
+}
+
+/// Something about an account's balance worth showing on a dashboard, identified by its path like root/Living/Food
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub path: String,
+    pub reason: AlertReason
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum AlertReason {
+    /// A leaf that's been overdrawn, like a credit card
+    Negative(Money),
+    /// A leaf that's been filled at least to its max
+    AtMax { balance: Money, max: Money },
+    /// A branch holding more than all of its leaves can, usually from deposits that had nowhere else to go
+    OverMax { balance: Money, max: Money }
+}
+
+/// What applying an action did, for callers that want to show more than whether it worked
+#[derive(Clone, Debug, PartialEq)]
+pub enum ActionOutcome {
+    /// A withdrawal or deposit straight into a leaf, with the leaf's new balance
+    Balance { account: String, balance: Money },
+    /// A withdrawal or deposit through a branch, with how much each of its leaves gained or lost
+    Distribution(Vec<(String, Money)>),
+    /// Anything else
+    Applied
+}
+
+/// Something about the tree's setup that's likely to make deposits go somewhere unexpected
+#[derive(Clone, Debug, PartialEq)]
+pub enum FundingWarning {
+    /// The named branch has children, but none with a fixed or flex inflow that takes anything, so deposits into it
+    /// are just split by how much room each child has
+    UnfundedBranch(String),
+    /// The named account's fixed inflow is more than its max, so it never gets the full amount
+    FixedOverMax { name: String, fixed: Money, max: Money }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum Action {
-    New { name: String, inflow: Inflow, parent: String, data: AccountType },
+    New {
+        name: String,
+        inflow: Inflow,
+        priority: i32,
+        parent: String,
+        data: AccountType,
+        note: Option<String>,
+        created: Option<NaiveDate>
+    },
     Remove { name: String },
-    Edit { name: String, inflow: Inflow, max: f64 },
-    Withdraw { account: String, amount: f64, date: NaiveDate },
-    Deposit { account: Option<String>, amount: f64, date: NaiveDate },
-    Transfer { from: String, to: Option<String>, amount: f64, date: NaiveDate }
+    Rename { name: String, new_name: String },
+    Move { name: String, new_parent: String },
+    Edit { name: String, inflow: Inflow, max: Money },
+    /// Move the source leaf's balance into the destination leaf, then remove the source
+    Merge { source: String, dest: String },
+    /// Turn a leaf into a branch of new leaves, each with an inflow and a max, and deposit its balance into them
+    Split { account: String, children: Vec<(String, Inflow, Money)> },
+    SetInflow { account: String, inflow: Inflow },
+    SetMax { account: String, max: Money },
+    /// Send what's left of deposits into the branch after inflows to one of its descendants, or split it up again if None
+    SetOverflow { account: String, overflow: Option<String> },
+    /// Split deposits anywhere in the tree into multiples of the unit, like 1.00 for a currency without cents
+    SetUnit { unit: Money },
+    /// Stop money from being taken out of the account and everything below it
+    Freeze { account: String },
+    Unfreeze { account: String },
+    /// Hide the account from its parent's totals and deposits while keeping it and its balance in the tree
+    Archive { account: String },
+    Withdraw { account: String, amount: Money, date: NaiveDate },
+    /// Withdraw everything from the account, or from each of its leaves for a branch
+    Empty { account: String, date: NaiveDate },
+    Deposit { account: Option<String>, amount: Money, date: NaiveDate },
+    /// Deposit into the named leaf, refusing a branch rather than handing the money out to its children
+    DepositLeaf { account: String, amount: Money, date: NaiveDate },
+    /// Deposit straight into several accounts at once, or none of them if any can't be found
+    MultiDeposit { entries: Vec<(String, Money)>, date: NaiveDate },
+    Transfer { from: String, to: Option<String>, amount: Money, date: NaiveDate },
+    /// Move whatever each leaf holds past its max into the named account
+    Sweep { to: String, date: NaiveDate },
+    /// Transfer between accounts held in different currencies, depositing the amount multiplied by the rate
+    TransferFx { from: String, to: String, amount: Money, rate: f64, date: NaiveDate },
+    /// Transfer into the named account, first creating it as a leaf under the parent if it doesn't exist yet
+    TransferToNew { from: String, to: String, parent: String, amount: Money, date: NaiveDate }
+}
+
+impl Action {
+    pub fn date(&self) -> Option<NaiveDate> {
+        match self {
+            Withdraw { date, .. } | Empty { date, .. } | Deposit { date, .. } | DepositLeaf { date, .. } | MultiDeposit { date, .. } | Sweep { date, .. } | Transfer { date, .. } | TransferFx { date, .. }
+                | TransferToNew { date, .. } =>
+                Some(*date),
+            _ => None
+        }
+    }
+
+    // Reject amounts and inflow weights that would corrupt balances or stall the flex distribution
+    fn validate(&self) -> Result<(), BudgetError> {
+        match self {
+            New { inflow, .. } | Edit { inflow, .. } | SetInflow { inflow, .. } => inflow.validate(),
+            Split { children, .. } => children.iter().try_for_each(|(_, inflow, max)| {
+                inflow.validate()?;
+                if *max < Money::ZERO {
+                    return Err(BudgetError::InvalidAmount(*max));
+                }
+                Ok(())
+            }),
+            MultiDeposit { entries, .. } => entries.iter().try_for_each(|(_, amount)| validate_amount(*amount)),
+            TransferFx { rate, .. } if !rate.is_finite() || *rate <= 0.0 => Err(BudgetError::InvalidRate(*rate)),
+            Withdraw { amount, .. } | Deposit { amount, .. } | DepositLeaf { amount, .. } | Transfer { amount, .. } | TransferFx { amount, .. }
+                | TransferToNew { amount, .. } => validate_amount(*amount),
+            SetMax { max, .. } if *max < Money::ZERO => Err(BudgetError::InvalidAmount(*max)),
+            SetUnit { unit } if *unit <= Money::ZERO || unit.is_unlimited() => Err(BudgetError::InvalidAmount(*unit)),
+            Remove { .. } | Rename { .. } | Move { .. } | Merge { .. } | SetMax { .. } | SetOverflow { .. } | SetUnit { .. } | Freeze { .. }
+                | Unfreeze { .. } | Archive { .. } | Empty { .. } | Sweep { .. } => Ok(())
+        }
+    }
+}
+
+impl Inflow {
+    fn validate(&self) -> Result<(), BudgetError> {
+        match *self {
+            // Negative fixed inflows are recurring debits, which have to be small enough to withdraw
+            Fixed(amount) if (-amount).is_unlimited() => Err(BudgetError::InvalidAmount(amount)),
+            Fixed(_) | FillToMax => Ok(()),
+            Flex(weight) | Percent(weight) if !weight.is_finite() || weight < 0.0 => Err(BudgetError::InvalidWeight(weight)),
+            Flex(_) | Percent(_) => Ok(())
+        }
+    }
+}
+
+/// Parses the compact `kind:amount` form, like `fixed:100`, `flex:3`, or `percent:10`, or just `fill` for FillToMax
+impl FromStr for Inflow {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Inflow, String> {
+        let error = || format!("Expected an inflow like fixed:100, flex:3, percent:10, or fill, found {}", string);
+        if string.eq_ignore_ascii_case("fill") {
+            return Ok(FillToMax);
+        }
+        let (kind, amount) = string.split_once(':').ok_or_else(error)?;
+        let weight = || amount.parse::<f64>().map_err(|_| error());
+        match kind.to_lowercase().as_str() {
+            "fixed" => amount.parse::<Money>().map(Fixed),
+            "flex" => weight().map(Flex),
+            "percent" => weight().map(Percent),
+            _ => Err(error())
+        }
+    }
+}
+
+/// Writes the same compact form that FromStr parses, like `fixed:100.00`, `flex:2`, `percent:10`, or `fill`
+///
+/// This is used instead of a form like `Fixed($100)` or `Flex(×2)` because an inflow doesn't know which currency its
+/// budget is in, so any symbol could be wrong, and because a displayed inflow can then be typed back in as it is.
+impl fmt::Display for Inflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fixed(amount) => write!(f, "fixed:{}", amount),
+            Flex(weight) => write!(f, "flex:{}", weight),
+            Percent(percent) => write!(f, "percent:{}", percent),
+            FillToMax => write!(f, "fill")
+        }
+    }
+}
+
+/// A short description like `Leaf` or `Branch(3 children)`
+impl fmt::Display for AccountType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Leaf { .. } => write!(f, "Leaf"),
+            Branch { children, .. } if children.len() == 1 => write!(f, "Branch(1 child)"),
+            Branch { children, .. } => write!(f, "Branch({} children)", children.len())
+        }
+    }
+}
+
+// The layout of a budget written in TOML, a list of accounts each under a parent listed before it
+#[derive(Deserialize)]
+struct TomlBudget {
+    #[serde(default)]
+    account: Vec<TomlAccount>
+}
+
+#[derive(Deserialize)]
+struct TomlAccount {
+    name: String,
+    #[serde(default = "root_name")]
+    parent: String,
+    // In the compact form parsed by Inflow's FromStr, like "flex:3"
+    inflow: String,
+    max: Option<String>,
+    #[serde(default)]
+    branch: bool,
+    note: Option<String>
+}
+
+fn root_name() -> String {
+    "root".to_owned()
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn cent() -> Money {
+    Money::CENT
+}
+
+fn is_cent(unit: &Money) -> bool {
+    *unit == Money::CENT
+}
+
+// Splits the amount into whole units in proportion to the weights, which can't all be zero. The units that don't
+// divide evenly go one each to the largest fractions of a unit, so nobody gets more than their exact share rounded up,
+// and anything smaller than a unit goes along with the first of them.
+fn split_by_weight(amount: Money, weights: &[f64], unit: Money) -> Vec<Money> {
+    let units = amount.cents() / unit.cents();
+    let total: f64 = weights.iter().sum();
+    let exact: Vec<f64> = weights.iter().map(|weight| units as f64 * weight / total).collect();
+    let mut shares: Vec<i64> = exact.iter().map(|share| share.floor() as i64).collect();
+    let leftover = units - shares.iter().sum::<i64>();
+    let mut by_fraction: Vec<usize> = (0..exact.len()).filter(|&index| weights[index] > 0.0).collect();
+    by_fraction.sort_by(|&a, &b| exact[b].fract().total_cmp(&exact[a].fract()));
+    for &index in by_fraction.iter().cycle().take(leftover.max(0) as usize) {
+        shares[index] += 1;
+    }
+    let mut shares: Vec<Money> = shares.into_iter().map(|share| Money::from_cents(share * unit.cents())).collect();
+    if let Some(&first) = by_fraction.first() {
+        shares[first] += Money::from_cents(amount.cents() % unit.cents());
+    }
+    shares
+}
+
+fn validate_amount(amount: Money) -> Result<(), BudgetError> {
+    if amount < Money::ZERO || amount.is_unlimited() {
+        Err(BudgetError::InvalidAmount(amount))
+    } else {
+        Ok(())
+    }
 }
 
 impl Account {
     pub fn new_root() -> Account {
         Account {
             name: "root".to_owned(),
-            data: Branch { children: Vec::new() }
+            data: Branch { children: Vec::new(), overflow: None, unit: Money::CENT },
+            note: None,
+            created: None,
+            last_activity: None,
+            frozen: false,
+            archived: false
+        }
+    }
+
+    /// Build a tree by applying each action in turn to a fresh root
+    pub fn replay(actions: Vec<Action>) -> Result<Account, BudgetError> {
+        let mut root = Account::new_root();
+        for action in actions {
+            root.apply(action)?;
+        }
+        Ok(root)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn data(&self) -> &AccountType {
+        &self.data
+    }
+
+    /// Rename the root, which can't go through the Rename action because it has no parent
+    ///
+    /// Lookups match the root first, so a name already used elsewhere in the tree would hide that account.
+    pub fn rename_root(&mut self, new_name: String) -> Result<(), BudgetError> {
+        if self.iter_all().skip(1).any(|account| account.name == new_name) {
+            return Err(BudgetError::DuplicateName(new_name));
+        }
+        self.name = new_name;
+        Ok(())
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        match self.data {
+            Leaf { .. } => true,
+            Branch { .. } => false
+        }
+    }
+
+    /// Every account in the tree, except for archived ones and anything below them
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { stack: vec![self], archived: false }
+    }
+
+    /// Every account in the tree, including archived ones
+    pub fn iter_all(&self) -> Iter<'_> {
+        Iter { stack: vec![self], archived: true }
+    }
+
+    pub fn is_archived(&self) -> bool {
+        self.archived
+    }
+
+    pub fn iter_leaves(&self) -> impl Iterator<Item = &Account> {
+        self.iter().filter(|account| account.is_leaf())
+    }
+
+    /// Every account in the tree that matches, in the same order as iter
+    pub fn filter<F: Fn(&Account) -> bool>(&self, predicate: F) -> Vec<&Account> {
+        self.iter().filter(|account| predicate(account)).collect()
+    }
+
+    /// How many levels of accounts are below this one, which is zero for a leaf or an empty branch
+    pub fn depth(&self) -> u32 {
+        self.children().iter()
+            .map(|child| child.account.depth() + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// How many accounts are in the tree, including this one
+    pub fn account_count(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// The immediate children of a branch, which a leaf doesn't have
+    pub fn children(&self) -> &[BranchEntry] {
+        match &self.data {
+            Leaf { .. } => &[],
+            Branch { children, .. } => children
+        }
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn load_from_file(path: &Path) -> Result<Account, String> {
+        let file = File::open(path)
+            .map_err(|e| format!("Could not open saved budget {}: {}", path.display(), e))?;
+        serde_json::from_reader(file)
+            .map_err(|e| format!("Could not read saved budget {}: {}", path.display(), e))
+    }
+
+    /// Build a tree from a TOML list of accounts, like
+    ///
+    /// ```toml
+    /// [[account]]
+    /// name = "Living"
+    /// inflow = "flex:3"
+    /// branch = true
+    ///
+    /// [[account]]
+    /// name = "Food"
+    /// parent = "Living"
+    /// inflow = "fixed:400"
+    /// max = "600"
+    /// ```
+    ///
+    /// Accounts go under the root unless they name a parent, which has to be a branch listed earlier. Leaves without
+    /// a max can grow without bound.
+    pub fn from_toml(source: &str) -> Result<Account, String> {
+        let budget: TomlBudget = toml::from_str(source).map_err(|e| format!("Could not read TOML budget: {}", e))?;
+        let mut root = Account::new_root();
+        for entry in budget.account {
+            let inflow = entry.inflow.parse::<Inflow>()?;
+            inflow.validate().map_err(|e| e.to_string())?;
+            let data = match (entry.branch, entry.max) {
+                (true, Some(_)) => return Err(BudgetError::BranchMax(entry.name).to_string()),
+                (true, None) => Branch { children: Vec::new(), overflow: None, unit: Money::CENT },
+                (false, max) => {
+                    let max = match max {
+                        Some(max) => max.parse::<Money>()?,
+                        None => Money::UNLIMITED
+                    };
+                    Leaf { balance: Money::ZERO, max, allow_negative: false, rollover: Rollover::Keep, goal: None, currency: None }
+                }
+            };
+            let account = Account { name: entry.name, data, note: entry.note, created: None, last_activity: None, frozen: false, archived: false };
+            root.find_account(&entry.parent)
+                .and_then(|parent| parent.add_child(account, inflow))
+                .map_err(|e| e.to_string())?;
         }
+        Ok(root)
     }
 
-    pub fn apply(&mut self, action: Action) -> Result<(), String> {
+    pub fn apply(&mut self, action: Action) -> Result<(), BudgetError> {
+        action.validate()?;
         match action {
-            New { name, inflow, parent, data } => {
-                let parent = self.find_child(&parent)
-                    .ok_or(format!("Could not find parent account {} to create account {}", parent, name))?;
-                let account = Account { name, data };
-                parent.add_child(account, inflow)
+            New { name, inflow, priority, parent, data, note, created } => {
+                let parent = self.find_account(&parent)
+                    .map_err(|error| error.when_not_found(|parent| BudgetError::ParentNotFound { parent, name: name.clone() }))?;
+                let account = Account { name, data, note, created, last_activity: None, frozen: false, archived: false }.rebuilt()?;
+                parent.add_entry(BranchEntry { account, inflow, priority })
             }
             Remove { name } => {
-                let balance = self.find_child(&name)
-                    .ok_or(format!("Could not find account {} to remove", name))?
-                    .balance();
+                let path = self.resolve_below_root(&name, "remove")?;
+                let account = self.at(&path);
+                if !account.children().is_empty() {
+                    return Err(BudgetError::HasChildren(name));
+                }
+                // Removing an account moves its balance out, which freezing is meant to stop
+                if account.frozen {
+                    return Err(BudgetError::Frozen(name));
+                }
+                // Detach the account first so its balance can't be deposited back into it
+                let balance = account.balance();
+                self.detach(&path);
                 self.deposit(balance);
-                self.remove(&name)?;
+                Ok(())
+            }
+            Merge { source, dest } => {
+                let source_path = self.resolve_below_root(&source, "merge")?;
+                let dest_path = self.resolve_deposit(&dest)?;
+                if source_path == dest_path {
+                    return Ok(());
+                }
+                let (amount, source_currency) = match &self.at(&source_path) {
+                    Account { frozen: true, .. } => return Err(BudgetError::Frozen(source)),
+                    Account { data: Leaf { balance, currency, .. }, .. } => (*balance, currency.clone()),
+                    Account { data: Branch { .. }, .. } => return Err(BudgetError::NotLeaf(source))
+                };
+                match &mut self.at(&dest_path).data {
+                    Leaf { currency, .. } if *currency != source_currency => return Err(BudgetError::MixedCurrencies(dest)),
+                    Leaf { balance, .. } => *balance += amount,
+                    Branch { .. } => return Err(BudgetError::NotLeaf(dest))
+                }
+                self.detach(&source_path);
+                Ok(())
+            }
+            Split { account, children } => {
+                // Splitting into nothing would leave the balance with nowhere to go
+                if children.is_empty() {
+                    return Ok(());
+                }
+                let path = self.resolve(&account)?;
+                let unit = self.unit();
+                let leaf = self.at(&path);
+                // The balance would move into children that aren't frozen
+                if leaf.frozen {
+                    return Err(BudgetError::Frozen(account));
+                }
+                let (balance, currency) = match &leaf.data {
+                    Leaf { balance, currency, .. } => (*balance, currency.clone()),
+                    Branch { .. } => return Err(BudgetError::NotLeaf(account))
+                };
+                if balance < Money::ZERO {
+                    return Err(BudgetError::InvalidAmount(balance));
+                }
+                // Build the branch on the side so a bad child leaves the leaf as it was
+                let data = Branch { children: Vec::new(), overflow: None, unit: Money::CENT };
+                let mut branch = Account { data, ..leaf.clone() };
+                branch.set_unit(unit);
+                for (name, inflow, max) in children {
+                    let data = Leaf { balance: Money::ZERO, max, allow_negative: false, rollover: Rollover::Keep, goal: None, currency: currency.clone() };
+                    let child = Account { name, data, note: None, created: leaf.created, last_activity: None, frozen: false, archived: false };
+                    branch.add_child(child, inflow)?;
+                }
+                branch.deposit(balance);
+                *leaf = branch;
+                Ok(())
+            }
+            Rename { name, new_name } => {
+                let path = self.resolve_below_root(&name, "rename")?;
+                let parent = self.at(&path[..path.len() - 1]);
+                if parent.children().iter().any(|child| child.account.name == new_name) {
+                    return Err(BudgetError::RenameTaken { name, new_name, parent: parent.name.clone() });
+                }
+                self.entry_at(&path).account.name = new_name;
+                Ok(())
+            }
+            SetInflow { account, inflow } => {
+                let path = self.resolve_below_root(&account, "change the inflow of")?;
+                let (name, parent) = path.split_last().expect("paths are never empty");
+                self.at(parent).check_percent(name, &inflow)?;
+                self.entry_at(&path).inflow = inflow;
+                Ok(())
+            }
+            SetMax { account, max } => {
+                match &mut self.find_account(&account)?.data {
+                    Leaf { max: node_max, .. } => {
+                        *node_max = max;
+                        Ok(())
+                    }
+                    Branch { .. } => Err(BudgetError::BranchMax(account))
+                }
+            }
+            SetOverflow { account, overflow: new_overflow } => {
+                let branch = self.find_account(&account)?;
+                if let Some(name) = &new_overflow {
+                    if branch.resolve(name)?.len() == 1 {
+                        return Err(BudgetError::AccountNotFound(name.clone()));
+                    }
+                }
+                match &mut branch.data {
+                    Leaf { .. } => Err(BudgetError::NotBranch(account)),
+                    Branch { overflow, .. } => {
+                        *overflow = new_overflow;
+                        Ok(())
+                    }
+                }
+            }
+            SetUnit { unit } => {
+                self.set_unit(unit);
                 Ok(())
             }
             Edit { name, inflow, max } => {
-                let mut data = self.find_child(&name)
-                    .ok_or(format!("Could not find account {} to remove", name))?
-                    .data.clone();
-                if let Leaf { max: node_max, .. } = &mut data {
+                let path = self.resolve_below_root(&name, "edit")?;
+                let (child_name, parent) = path.split_last().expect("paths are never empty");
+                self.at(parent).check_percent(child_name, &inflow)?;
+                let entry = self.entry_at(&path);
+                if let Leaf { max: node_max, .. } = &mut entry.account.data {
                     *node_max = max;
                 }
-                let parent = self.remove(&name)?;
-                self.apply(New { name, inflow, parent: parent.clone(), data })
+                entry.inflow = inflow;
+                Ok(())
+            }
+            Move { name, new_parent } => {
+                let path = self.resolve_below_root(&name, "move")?;
+                let parent_path = self.resolve(&new_parent)
+                    .map_err(|error| error.when_not_found(|new_parent| BudgetError::MoveTargetNotFound { name: name.clone(), new_parent }))?;
+                if parent_path.starts_with(&path) {
+                    return Err(BudgetError::MoveIntoSelf { name, new_parent });
+                }
+                let (child_name, old_parent) = path.split_last().expect("paths are never empty");
+                if parent_path[..] == old_parent[..] {
+                    return Ok(());
+                }
+                // Make sure the new parent will take the account before detaching it from the old one
+                let inflow = self.entry_at(&path).inflow.clone();
+                self.at(&parent_path).check_child(child_name, &inflow).map_err(|error| match error {
+                    BudgetError::AddChildToLeaf => BudgetError::MoveIntoLeaf { name: name.clone(), new_parent: new_parent.clone() },
+                    other => other
+                })?;
+                let entry = self.detach(&path);
+                self.at(&parent_path).add_entry(entry)
             }
-            Withdraw { account, amount, .. } => {
-                let parent = self.find_child(&account)
-                    .ok_or(format!("Could not find parent account {} to withdraw from", account))?;
-                parent.withdraw(amount)?;
+            Withdraw { account, amount, date } => self.find_account(&account)
+                .map_err(|error| error.when_not_found(|name| BudgetError::TargetNotFound { name, action: "withdraw from" }))?
+                .with_activity(date, |account| account.withdraw(amount)),
+            Empty { account, date } => {
+                let target = self.find_account(&account)?;
+                if target.frozen {
+                    return Err(BudgetError::Frozen(account));
+                }
+                target.with_activity(date, Account::empty);
+                Ok(())
+            }
+            Freeze { account } => {
+                self.find_account(&account)?.set_frozen(true);
+                Ok(())
+            }
+            Unfreeze { account } => {
+                self.find_account(&account)?.set_frozen(false);
+                Ok(())
+            }
+            Archive { account } => {
+                let path = self.resolve_below_root(&account, "archive")?;
+                self.at(&path).archived = true;
                 Ok(())
             }
-            Deposit { account, amount, .. } => {
+            Deposit { account, amount, date } => {
                 let account = match account {
-                    Some(parent) => self.find_child(&parent)
-                        .ok_or(format!("Could not find parent account {} to deposit to", parent))?,
+                    Some(account) => {
+                        let path = self.resolve_deposit(&account)
+                            .map_err(|error| error.when_not_found(|name| BudgetError::TargetNotFound { name, action: "deposit to" }))?;
+                        self.at(&path)
+                    }
                     None => self
                 };
-                account.deposit(amount);
+                account.with_activity(date, |account| account.deposit(amount));
+                Ok(())
+            }
+            DepositLeaf { account: name, amount, date } => {
+                let path = self.resolve_deposit(&name)?;
+                let account = self.at(&path);
+                if !account.is_leaf() {
+                    return Err(BudgetError::NotLeaf(name));
+                }
+                account.with_activity(date, |account| account.deposit(amount));
+                Ok(())
+            }
+            MultiDeposit { entries, date } => {
+                // Deposit into a copy so a failure partway through leaves this tree untouched
+                let mut staged = self.clone();
+                for (account, amount) in entries {
+                    staged.apply(Action::Deposit { account: Some(account), amount, date })?;
+                }
+                *self = staged;
+                Ok(())
+            }
+            Sweep { to, date } => {
+                let path = self.resolve_deposit(&to)?;
+                self.with_activity(date, |root| {
+                    let excess = root.take_excess(&path);
+                    root.at(&path).deposit(excess);
+                });
                 Ok(())
             }
             Transfer { from, to, amount, date } => {
+                // Make sure there's somewhere to deposit before withdrawing, so the money can't go missing
+                if let Some(to) = &to {
+                    let to_path = self.resolve_deposit(to)?;
+                    let from_path = self.resolve(&from)?;
+                    // Moving money between currencies takes a rate, which only TransferFx has
+                    let currency = |account: &Account| match &account.data {
+                        Leaf { currency, .. } => Some(currency.clone()),
+                        Branch { .. } => None
+                    };
+                    if let (Some(from_currency), Some(to_currency)) = (currency(self.at(&from_path)), currency(self.at(&to_path))) {
+                        if from_currency != to_currency {
+                            return Err(BudgetError::MixedCurrencies(to.clone()));
+                        }
+                    }
+                }
                 self.apply(Action::Withdraw { account: from, amount, date })?;
                 self.apply(Action::Deposit { account: to, amount, date })
             }
+            TransferFx { from, to, amount, rate, date } => {
+                // Make sure there's somewhere to deposit, and that the converted amount can be deposited, before withdrawing
+                self.resolve_deposit(&to)?;
+                let converted = amount.convert(rate);
+                validate_amount(converted)?;
+                self.apply(Action::Withdraw { account: from, amount, date })?;
+                self.apply(Action::Deposit { account: Some(to), amount: converted, date })
+            }
+            TransferToNew { from, to, parent, amount, date } => {
+                match self.resolve(&to) {
+                    Ok(_) => return self.apply(Action::Transfer { from, to: Some(to), amount, date }),
+                    Err(BudgetError::AccountNotFound(_)) if !to.contains('/') => (),
+                    Err(e) => return Err(e)
+                }
+                // A flex weight of zero and a max of what it starts with keep the new account out of future deposits until
+                // it's edited
+                let inflow = Flex(0.0);
+                let parent = self.resolve(&parent)?;
+                self.at(&parent).check_child(&to, &inflow)?;
+                self.apply(Action::Withdraw { account: from, amount, date })?;
+                let data = Leaf { balance: amount, max: amount, allow_negative: false, rollover: Rollover::Keep, goal: None, currency: None };
+                let account = Account { name: to, data, note: None, created: Some(date), last_activity: Some(date), frozen: false, archived: false };
+                self.at(&parent).add_entry(BranchEntry { account, inflow, priority: 0 })
+            }
         }
     }
 
-    pub fn balance(&self) -> f64 {
-        match self.data {
-            Leaf { balance, .. } => balance,
-            Branch { ref children } => children
-                .iter()
-                .map(|BranchEntry { account, .. }| account.balance())
-                .sum()
+    /// Apply each action in turn, putting the tree back the way it was if any of them fail
+    pub fn apply_all(&mut self, actions: Vec<Action>) -> Result<(), BudgetError> {
+        let snapshot = self.clone();
+        for action in actions {
+            if let Err(e) = self.apply(action) {
+                *self = snapshot;
+                return Err(e);
+            }
         }
+        Ok(())
     }
 
-    fn remove(&mut self, name: &str) -> Result<String, String> {
-        match &mut self.data {
-            Leaf { .. } => Err(format!("{} not found to remove", name)),
-            Branch { ref mut children } => {
-                let len = children.len();
-                children.retain(|child| child.account.name != name);
-                if len != children.len() {
-                    return Ok(self.name.clone());
-                }
-                for child in children.iter_mut() {
-                    if let Ok(name) = child.account.remove(name.clone()) {
-                        return Ok(name);
-                    }
-                }
-                Err(format!("{} not found to remove", name))
-            }
+    /// Apply the action, reporting where the money went for deposits and withdrawals
+    pub fn apply_reporting(&mut self, action: Action) -> Result<ActionOutcome, BudgetError> {
+        let target = match &action {
+            Withdraw { account, .. } | DepositLeaf { account, .. } => account.clone(),
+            Deposit { account, .. } => account.clone().unwrap_or_else(|| self.name.clone()),
+            _ => return self.apply(action).map(|_| ActionOutcome::Applied)
+        };
+        let mut before = self.clone();
+        self.apply(action)?;
+        // Deposits and withdrawals don't change the structure, so the path leads to the same account in both trees
+        let path = self.resolve(&target)?;
+        let after = self.at(&path);
+        if let Leaf { balance, .. } = after.data {
+            return Ok(ActionOutcome::Balance { account: target, balance });
         }
+        let changes = after.iter_leaves()
+            .zip(before.at(&path).iter_leaves())
+            .map(|(after, before)| (after.name.clone(), after.balance() - before.balance()))
+            .filter(|(_, change)| *change != Money::ZERO)
+            .collect();
+        Ok(ActionOutcome::Distribution(changes))
     }
 
-    pub fn deposit(&mut self, amount: f64) {
-        match self.data {
-            Leaf { ref mut balance, .. } => *balance += amount,
-            Branch { ref mut children } => {
-                // Make fixed deposits
-                let mut amount = children.iter_mut()
-                    .fold(amount, |amount, child| child.make_fixed_deposit(amount));
-                // Make flex deposits
-                let mut total_flex: f64 = children.iter().map(BranchEntry::get_flex).sum();
-                while total_flex != 0.0 && amount > 0.01 {
-                    let per_flex = amount / total_flex;
-                    amount = children.iter_mut()
-                        .fold(amount, |amount, child| child.make_flex_deposit(amount, per_flex));
-                    total_flex = children.iter().map(BranchEntry::get_flex).sum();
-                }
-                // Give up and redistribute
-                let remaining = amount / children.len() as f64;
-                if remaining > 0.01 {
-                    children.iter_mut().for_each(|child| child.account.deposit(remaining));
-                }
-            }
-        }
+    /// Apply the action, then call the hook with it and the resulting tree if it succeeded, as for logging
+    pub fn apply_with_hook<F: FnMut(&Action, &Account)>(&mut self, action: Action, hook: &mut F) -> Result<(), BudgetError> {
+        self.apply(action.clone())?;
+        hook(&action, self);
+        Ok(())
     }
 
-    pub fn withdraw(&mut self, amount: f64) -> Result<(), String> {
+    pub fn balance(&self) -> Money {
         match self.data {
-            Leaf { ref mut balance, .. } => Ok(*balance -= amount),
-            _ => Err("Cannot withdraw from a branch node".to_owned())
+            Leaf { balance, .. } => balance,
+            Branch { ref children, .. } => children
+                .iter()
+                .filter(|child| child.active())
+                .map(|BranchEntry { account, .. }| account.balance())
+                .sum()
         }
     }
 
-    pub fn find_child(&mut self, name: &str) -> Option<&mut Account> {
-        if self.name == name {
-            return Some(self);
-        }
-        match &mut self.data {
-            Leaf { .. } => None,
-            Branch { children } =>  {
-                for child in children.iter_mut() {
-                    if child.account.name == name {
-                        return Some(&mut child.account)
-                    } else {
-                        if let Some(account) = child.account.find_child(name) {
-                            return Some(account);
-                        }
-                    }
-                }
-                None
+    /// The balance, as long as every leaf it adds up is held in the same currency
+    pub fn checked_balance(&self) -> Result<Money, BudgetError> {
+        let mut currencies = self.iter().filter_map(|account| match &account.data {
+            Leaf { currency, .. } => Some(currency),
+            Branch { .. } => None
+        });
+        if let Some(first) = currencies.next() {
+            if currencies.any(|currency| currency != first) {
+                return Err(BudgetError::MixedCurrencies(self.name.clone()));
             }
         }
+        Ok(self.balance())
+    }
+
+    /// When this account, or the most recently active account inside it, last had its balance changed
+    pub fn last_activity(&self) -> Option<NaiveDate> {
+        self.iter().filter_map(|account| account.last_activity).max()
     }
 
-    pub fn add_child(&mut self, account: Account, inflow: Inflow) -> Result<(), String> {
+    // Make a change to the balances in this subtree, marking each leaf it changed as active on the date
+    fn with_activity<T>(&mut self, date: NaiveDate, change: impl FnOnce(&mut Account) -> T) -> T {
+        let before: Vec<Money> = self.iter_leaves().map(Account::balance).collect();
+        let result = change(self);
+        self.mark_activity(&mut before.into_iter(), date);
+        result
+    }
+
+    // Visits the leaves in the same order as iter_leaves
+    fn mark_activity(&mut self, before: &mut impl Iterator<Item = Money>, date: NaiveDate) {
         match &mut self.data {
-            Leaf { .. } => Err("Cannot add a child to a leaf account".to_owned()),
-            Branch { children } => {
-                children.push(BranchEntry { account, inflow });
-                Ok(())
+            Leaf { balance, .. } => if before.next() != Some(*balance) {
+                self.last_activity = Some(date);
             }
+            Branch { children, .. } => children.iter_mut()
+                .filter(|child| child.active())
+                .for_each(|child| child.account.mark_activity(before, date))
         }
     }
 
-    fn print_level(&self, f: &mut fmt::Formatter, level: u32, inflow: Inflow) -> fmt::Result {
-        print!("{:?}:\t", inflow);
-        for _ in 0..level {
-            print!("  ");
-        }
-        println!("{}: {:.2}", self.name, self.balance());
+    /// The most this account can hold; a branch can hold as much as all of its children put together
+    ///
+    /// For a branch this is the total of every leaf's max below it, so it's unlimited if any of those leaves is.
+    pub fn max(&self) -> Money {
         match &self.data {
-            Leaf {..}  => Ok(()),
-            Branch { children } => {
-                for child in children {
-                    child.account.print_level(f, level + 1, child.inflow.clone())?
-                }
-                Ok(())
-            }
+            Leaf { max, .. } => *max,
+            Branch { children, .. } => children.iter().filter(|child| child.active()).map(|child| child.account.max()).sum()
         }
     }
 
-    pub fn diff(&self, other: &Account) -> Result<Account, String> {
-        let data = match (&self.data, &other.data) {
-            (Leaf { balance: end, max }, Leaf { balance: start, .. }) => {
-                Leaf {
-                    balance: end - start,
-                    max: *max
-                }
-            }
-            (Branch { children: start }, Branch { children: end }) => {
-                let mut children = Vec::new();
-                for start_child in start {
-                    for end_child in end {
-                        let end_child = &end_child.account;
-                        let start_child = &start_child.account;
-                        if end_child.name == start_child.name {
-                            children.push(BranchEntry {
-                                account: end_child.diff(start_child)?,
-                                inflow: Inflow::Fixed(0.0)
-                            });
-                        }
-                    }
-                }
-                for end_child in end {
-                    let end_child = &end_child.account;
-                    if children.iter().filter(|child| child.account.name == end_child.name).count() == 0 {
-                            children.push(BranchEntry {
-                                account: end_child.clone(),
-                                inflow: Inflow::Fixed(0.0)
-                            });
-                    }
-                }
-
-                Branch { children }
-            }
-            (_, _) => return Err("Tried to diff accounts of different types".to_owned())
-        };
-
-        Ok(Account {
-            name: self.name.clone(),
-            data
-        })
+    pub fn remaining_to_max(&self) -> Money {
+        self.max() - self.balance()
     }
-}
 
-impl fmt::Display for Account {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.print_level(f, 0, Inflow::Flex(1.0))
+    pub fn is_full(&self) -> bool {
+        self.remaining_to_max() <= Money::ZERO
     }
-}
 
-impl BranchEntry {
-    fn max(&self) -> f64 {
-        match &self.account.data {
-            Leaf { max, .. } => *max,
-            Branch { children } => children.iter().map(BranchEntry::max).sum()
+    /// How full the account is from 0 to 1, for branches by the totals of their leaves, as for a progress bar
+    ///
+    /// An account with no max counts as full once it has anything in it, and one with an unlimited max never fills.
+    pub fn fill_ratio(&self) -> f64 {
+        let (balance, max) = (self.balance(), self.max());
+        if max == Money::ZERO {
+            return if balance > Money::ZERO { 1.0 } else { 0.0 };
         }
+        (balance.cents() as f64 / max.cents() as f64).clamp(0.0, 1.0)
     }
 
-    fn until_max(&self) -> f64 {
-        self.max() - self.account.balance()
+    /// How much of the balance is beyond what the account and its descendants can hold
+    pub fn unallocated(&self) -> Money {
+        (self.balance() - self.max()).max(Money::ZERO)
+    }
+
+    /// Add money to the account
+    ///
+    /// A leaf takes all of it, even past its max. A branch funds its children through their inflows without going
+    /// past their maxes, then splits whatever is left between them by how much room each has left. Only once they're
+    /// all full does the rest go to the branch's overflow account, or get split evenly regardless of max if it doesn't
+    /// have one. Children with a flex weight of zero are left out of both splits, unless every child has one.
+    pub fn deposit(&mut self, amount: Money) {
+        let amount = self.fund_children(amount, false);
+        let leftover = self.split_by_room(amount);
+        if leftover <= Money::ZERO {
+            return;
+        }
+        match self.overflow_path() {
+            Some(path) => self.at(&path).deposit(leftover),
+            None => self.split_evenly(leftover)
+        }
+    }
+
+    // The children that a deposit's leftovers are split between
+    fn split_targets(&mut self) -> Vec<&mut BranchEntry> {
+        let mut children: Vec<&mut BranchEntry> = match &mut self.data {
+            Leaf { .. } => Vec::new(),
+            Branch { children, .. } => children.iter_mut().filter(|child| child.active()).collect()
+        };
+        if children.iter().any(|child| child.inflow != Flex(0.0)) {
+            children.retain(|child| child.inflow != Flex(0.0));
+        }
+        children
+    }
+
+    // Split the amount between the children by how much room each has left, returning whatever doesn't fit
+    fn split_by_room(&mut self, mut amount: Money) -> Money {
+        let unit = self.unit();
+        let mut children = self.split_targets();
+        if amount <= Money::ZERO || children.is_empty() {
+            return amount;
+        }
+        let rooms: Vec<Money> = children.iter().map(|child| child.until_max().max(Money::ZERO)).collect();
+        let total_room: Money = rooms.iter().copied().sum();
+        if amount <= total_room {
+            let weights: Vec<f64> = rooms.iter().map(|room| room.cents() as f64).collect();
+            for (child, share) in children.iter_mut().zip(split_by_weight(amount, &weights, unit)) {
+                child.account.deposit(share);
+            }
+            return Money::ZERO;
+        }
+        for (child, room) in children.iter_mut().zip(rooms) {
+            child.account.deposit(room);
+            amount -= room;
+        }
+        amount
+    }
+
+    // Give up and redistribute in whole units, with the units that don't divide evenly going one each to the earliest
+    // children and anything smaller than a unit going to the first
+    fn split_evenly(&mut self, amount: Money) {
+        let unit = self.unit();
+        let mut children = self.split_targets();
+        if children.is_empty() {
+            return;
+        }
+        let count = children.len() as i64;
+        let units = amount.cents() / unit.cents();
+        let share = Money::from_cents(units / count * unit.cents());
+        let leftover = (units % count) as usize;
+        for (index, child) in children.iter_mut().enumerate() {
+            let extra = if index < leftover { unit } else { Money::ZERO };
+            let remainder = if index == 0 { Money::from_cents(amount.cents() % unit.cents()) } else { Money::ZERO };
+            child.account.deposit(share + extra + remainder);
+        }
+    }
+
+    /// Deposit as usual, returning how much of it had to go past maxes because there wasn't room for it anywhere
+    pub fn deposit_reporting_excess(&mut self, amount: Money) -> Money {
+        let before = self.excess();
+        self.deposit(amount);
+        self.excess() - before
+    }
+
+    // The total that leaves hold past their maxes
+    fn excess(&self) -> Money {
+        self.iter()
+            .filter(|account| account.is_leaf())
+            .map(|account| (account.balance() - account.max()).max(Money::ZERO))
+            .sum()
+    }
+
+    // The path to the branch's overflow account, unless it isn't set or can't currently take deposits, e.g. because
+    // it was archived or renamed since
+    fn overflow_path(&mut self) -> Option<Vec<String>> {
+        let name = match &self.data {
+            Branch { overflow: Some(name), .. } => name.clone(),
+            _ => return None
+        };
+        self.resolve_deposit(&name).ok().filter(|path| path.len() > 1)
+    }
+
+    // Hand a deposit out through the children's inflows, returning whatever they couldn't take
+    //
+    // When capped, each child is funded with deposit_capped instead of deposit, so nothing further down goes past its
+    // max either.
+    fn fund_children(&mut self, amount: Money, capped: bool) -> Money {
+        match self.data {
+            Leaf { ref mut balance, .. } => {
+                *balance += amount;
+                Money::ZERO
+            }
+            Branch { ref mut children, unit, .. } => {
+                // Fund each priority tier in turn, highest first
+                let incoming = amount;
+                let mut priorities: Vec<i32> = children.iter().filter(|child| child.active()).map(|child| child.priority).collect();
+                priorities.sort_unstable_by(|a, b| b.cmp(a));
+                priorities.dedup();
+                priorities.into_iter().fold(amount, |amount, priority| {
+                    let mut tier: Vec<&mut BranchEntry> = children.iter_mut()
+                        .filter(|child| child.active() && child.priority == priority)
+                        .collect();
+                    BranchEntry::fund_tier(&mut tier, amount, incoming, capped, unit)
+                })
+            }
+        }
+    }
+
+    /// Deposit without going past any maxes, returning whatever couldn't be placed
+    ///
+    /// A leaf takes up to its max. A branch funds its children through their inflows, then splits what's left evenly
+    /// between the children with room, each capped the same way, until it's all placed or none have room.
+    pub fn deposit_capped(&mut self, amount: Money) -> Money {
+        if let Leaf { .. } = self.data {
+            let take = amount.min(self.remaining_to_max().max(Money::ZERO));
+            self.fund_children(take, true);
+            return amount - take;
+        }
+        let mut amount = self.fund_children(amount, true);
+        if let Branch { children, unit, .. } = &mut self.data {
+            let unit = *unit;
+            let mut open: Vec<&mut BranchEntry> = children.iter_mut().filter(|child| child.active() && !child.at_max()).collect();
+            // Every pass either places everything or closes a child that had something handed back
+            while amount > Money::ZERO && !open.is_empty() {
+                // Split in whole units, with anything smaller than a unit going to the first child
+                let count = open.len() as i64;
+                let units = amount.cents() / unit.cents();
+                let share = Money::from_cents(units / count * unit.cents());
+                let leftover = (units % count) as usize;
+                let remainder = Money::from_cents(amount.cents() % unit.cents());
+                amount = Money::ZERO;
+                let mut still_open = Vec::new();
+                for (index, child) in open.into_iter().enumerate() {
+                    let extra = if index < leftover { unit } else { Money::ZERO };
+                    let extra = if index == 0 { extra + remainder } else { extra };
+                    let returned = child.account.deposit_capped(share + extra);
+                    if returned > Money::ZERO {
+                        amount += returned;
+                    } else {
+                        still_open.push(child);
+                    }
+                }
+                open = still_open;
+            }
+        }
+        amount
+    }
+
+    /// Deposit like normal, except that whatever the children can't take goes to the named descendant, past its max,
+    /// instead of being spread between the children
+    pub fn deposit_forced(&mut self, amount: Money, into: &str) -> Result<(), BudgetError> {
+        let path = self.resolve(into)?;
+        let leftover = self.fund_children(amount, false);
+        self.at(&path).deposit(leftover);
+        Ok(())
+    }
+
+    /// Deposit and report how much each leaf received
+    pub fn deposit_detailed(&mut self, amount: Money) -> Vec<(String, Money)> {
+        let before: Vec<Money> = self.iter_leaves().map(Account::balance).collect();
+        self.deposit(amount);
+        self.iter_leaves()
+            .zip(before)
+            .map(|(after, before)| (after.name.clone(), after.balance() - before))
+            .filter(|(_, received)| *received != Money::ZERO)
+            .collect()
+    }
+
+    /// Preview where a deposit would go without making it
+    pub fn plan_deposit(&self, amount: Money) -> Vec<(String, Money)> {
+        self.clone().deposit_detailed(amount)
+    }
+
+    /// Start a new month: reset the leaves that don't roll over, fund the tree with the month's income, then charge
+    /// each recurring debit once
+    pub fn new_month(&mut self, income: Money) {
+        self.reset_for_month();
+        self.deposit(income);
+        self.charge_debits();
+    }
+
+    fn charge_debits(&mut self) {
+        if let Branch { children, .. } = &mut self.data {
+            for child in children.iter_mut().filter(|child| child.active()) {
+                child.charge_debit();
+                child.account.charge_debits();
+            }
+        }
+    }
+
+    fn reset_for_month(&mut self) {
+        match &mut self.data {
+            Leaf { balance, rollover: Rollover::Reset, .. } => *balance = Money::ZERO,
+            Leaf { .. } => (),
+            Branch { children, .. } => children.iter_mut().for_each(|child| child.account.reset_for_month())
+        }
+    }
+
+    /// Zero every leaf's balance, keeping the structure, maxes, and inflows, as for the start of a new budget year
+    pub fn reset_balances(&mut self) {
+        match &mut self.data {
+            Leaf { balance, .. } => *balance = Money::ZERO,
+            Branch { children, .. } => children.iter_mut().for_each(|child| child.account.reset_balances())
+        }
+    }
+
+    /// Remove every branch below this account that has no children, including ones left empty by removing others
+    pub fn prune_empty(&mut self) {
+        if let Branch { children, .. } = &mut self.data {
+            children.iter_mut().for_each(|child| child.account.prune_empty());
+            children.retain(|child| child.account.is_leaf() || !child.account.children().is_empty());
+        }
+    }
+
+    /// Put a branch's immediate children in alphabetical order, leaving grandchildren as they are
+    ///
+    /// Cents left over after splitting a deposit go to the earliest children, so reordering can change who gets them.
+    pub fn sort_children_by_name(&mut self) {
+        if let Branch { children, .. } = &mut self.data {
+            children.sort_by(|a, b| a.account.name.cmp(&b.account.name));
+        }
+    }
+
+    /// Put a branch's immediate children in order of largest balance first, leaving grandchildren as they are
+    ///
+    /// As with sorting by name, this changes which children get the leftover cents from later deposits.
+    pub fn sort_children_by_balance(&mut self) {
+        if let Branch { children, .. } = &mut self.data {
+            children.sort_by_key(|child| std::cmp::Reverse(child.account.balance()));
+        }
+    }
+
+    /// The least a deposit has to be for every fixed child in the tree to get its full amount, or as much as fits
+    /// under its max
+    ///
+    /// A fixed child's amount pays for whatever is below it, and FillToMax children need all of their room. Flex and
+    /// percent children don't count themselves, only the fixed children somewhere below them, and recurring debits
+    /// don't count at all.
+    pub fn required_income(&self) -> Money {
+        self.children().iter()
+            .filter(|child| child.active())
+            .map(|child| {
+                let room = child.until_max().max(Money::ZERO);
+                match child.inflow {
+                    Fixed(amount) if amount > Money::ZERO => amount.min(room),
+                    Fixed(_) => Money::ZERO,
+                    FillToMax => room,
+                    Flex(_) | Percent(_) => child.account.required_income()
+                }
+            })
+            .sum()
+    }
+
+    /// How much needs to be deposited each month from today to meet this leaf's goal
+    ///
+    /// This is zero once the goal is met, and None if there is no goal or its deadline has passed.
+    pub fn required_monthly(&self, today: NaiveDate) -> Option<Money> {
+        let (balance, goal) = match &self.data {
+            Leaf { balance, goal: Some(goal), .. } => (*balance, goal),
+            _ => return None
+        };
+        if balance >= goal.target {
+            return Some(Money::ZERO);
+        }
+        if goal.by <= today {
+            return None;
+        }
+        let months = (goal.by.year() - today.year()) as i64 * 12 + goal.by.month() as i64 - today.month() as i64;
+        // A deadline later this month still leaves one deposit to make
+        let months = months.max(1);
+        // Round up so the deposits never fall short of the target
+        let shortfall = (goal.target - balance).cents();
+        Some(Money::from_cents((shortfall + months - 1) / months))
+    }
+
+    /// How many monthly deposits of the amount it takes for this leaf to reach its goal's target, ignoring the deadline
+    ///
+    /// This is zero once the goal is met, and None if there is no goal or the deposits would never reach it.
+    pub fn months_to_goal(&self, monthly: Money) -> Option<u32> {
+        let (balance, goal) = match &self.data {
+            Leaf { balance, goal: Some(goal), .. } => (*balance, goal),
+            _ => return None
+        };
+        if balance >= goal.target {
+            return Some(0);
+        }
+        if monthly <= Money::ZERO {
+            return None;
+        }
+        let shortfall = (goal.target - balance).cents();
+        u32::try_from((shortfall + monthly.cents() - 1) / monthly.cents()).ok()
+    }
+
+    /// Take money out of a leaf, or out of a branch's children in order, skipping any that are frozen
+    pub fn withdraw(&mut self, amount: Money) -> Result<(), BudgetError> {
+        validate_amount(amount)?;
+        if self.frozen {
+            return Err(BudgetError::Frozen(self.name.clone()));
+        }
+        let available = self.withdrawable();
+        match self.data {
+            Leaf { ref mut balance, allow_negative, .. } => {
+                if !allow_negative && available < amount {
+                    return Err(BudgetError::InsufficientFunds { available, requested: amount });
+                }
+                *balance -= amount;
+                Ok(())
+            }
+            Branch { ref mut children, .. } => {
+                if available < amount {
+                    return Err(BudgetError::InsufficientFunds { available, requested: amount });
+                }
+                // Draw from each child in order until the amount is covered
+                let mut remaining = amount;
+                for child in children.iter_mut().filter(|child| child.active()) {
+                    let take = child.account.withdrawable().max(Money::ZERO).min(remaining);
+                    if take > Money::ZERO {
+                        child.account.withdraw(take)?;
+                        remaining -= take;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Bring every overdrawn leaf back up to zero by withdrawing the total from the named source
+    ///
+    /// Leaves inside the source are left alone, since they'd only be covering themselves. Nothing changes if the
+    /// source can't cover all of them.
+    pub fn cover_negatives(&mut self, from: &str) -> Result<(), BudgetError> {
+        let source = self.resolve(from)?;
+        let overdrawn: Vec<(Vec<String>, Money)> = self.paths().into_iter()
+            .filter(|(_, account)| account.is_leaf() && account.balance() < Money::ZERO)
+            .map(|(path, account)| (path.into_iter().map(str::to_owned).collect::<Vec<_>>(), -account.balance()))
+            .filter(|(path, _)| !path.starts_with(&source))
+            .collect();
+        let total: Money = overdrawn.iter().map(|(_, owed)| *owed).sum();
+        if total == Money::ZERO {
+            return Ok(());
+        }
+        self.at(&source).withdraw(total)?;
+        for (path, owed) in overdrawn {
+            self.at(&path).deposit(owed);
+        }
+        Ok(())
+    }
+
+    // Bring every leaf that's past its max back down to it, returning the total taken
+    //
+    // The account at the path, which starts from this account, is left alone along with everything below it, as are
+    // frozen and archived accounts.
+    fn take_excess(&mut self, skip: &[String]) -> Money {
+        if skip.len() == 1 && skip[0] == self.name {
+            return Money::ZERO;
+        }
+        let skip = match skip.split_first() {
+            Some((first, rest)) if *first == self.name => rest,
+            _ => &[]
+        };
+        match &mut self.data {
+            _ if self.frozen => Money::ZERO,
+            Leaf { balance, max, .. } => {
+                let excess = (*balance - *max).max(Money::ZERO);
+                *balance -= excess;
+                excess
+            }
+            Branch { children, .. } => children.iter_mut()
+                .filter(|child| child.active())
+                .map(|child| child.account.take_excess(skip))
+                .sum()
+        }
+    }
+
+    // How much can be withdrawn without touching anything frozen
+    fn withdrawable(&self) -> Money {
+        match &self.data {
+            _ if self.frozen => Money::ZERO,
+            Leaf { balance, .. } => *balance,
+            Branch { children, .. } => children.iter().filter(|child| child.active()).map(|child| child.account.withdrawable()).sum()
+        }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// The smallest amount deposits into the account are split into, a cent unless Action::SetUnit changed it
+    pub fn unit(&self) -> Money {
+        match self.data {
+            Leaf { .. } => Money::CENT,
+            Branch { unit, .. } => unit
+        }
+    }
+
+    fn set_unit(&mut self, new_unit: Money) {
+        if let Branch { children, unit, .. } = &mut self.data {
+            *unit = new_unit;
+            children.iter_mut().for_each(|child| child.account.set_unit(new_unit));
+        }
+    }
+
+    fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+        if let Branch { children, .. } = &mut self.data {
+            children.iter_mut().for_each(|child| child.account.set_frozen(frozen));
+        }
+    }
+
+    /// Withdraw every leaf's balance, returning the total taken out
+    ///
+    /// Leaves that are overdrawn keep their negative balances, since there's nothing to withdraw from them, and frozen
+    /// leaves keep theirs too.
+    pub fn empty(&mut self) -> Money {
+        match &mut self.data {
+            _ if self.frozen => Money::ZERO,
+            Leaf { balance, .. } => {
+                let taken = (*balance).max(Money::ZERO);
+                *balance -= taken;
+                taken
+            }
+            Branch { children, .. } => children.iter_mut().filter(|child| child.active()).map(|child| child.account.empty()).sum()
+        }
+    }
+
+    pub fn find_child(&mut self, name: &str) -> Option<&mut Account> {
+        if self.name == name {
+            return Some(self);
+        }
+        match &mut self.data {
+            Leaf { .. } => None,
+            Branch { children, .. } =>  {
+                for child in children.iter_mut() {
+                    if child.account.name == name {
+                        return Some(&mut child.account)
+                    } else {
+                        if let Some(account) = child.account.find_child(name) {
+                            return Some(account);
+                        }
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    /// The other accounts in the same branch as the named account, in order, or none for the root
+    pub fn siblings_of(&self, name: &str) -> Vec<&Account> {
+        self.parent_of(name)
+            .map(|parent| parent.children().iter().map(BranchEntry::account).filter(|account| account.name != name).collect())
+            .unwrap_or_default()
+    }
+
+    // The same search as find_parent, without needing to change what it finds
+    fn parent_of(&self, name: &str) -> Option<&Account> {
+        if self.children().iter().any(|child| child.account.name == name) {
+            return Some(self);
+        }
+        self.children().iter().find_map(|child| child.account.parent_of(name))
+    }
+
+    /// Find the branch that directly contains the named account, which the root never has
+    pub fn find_parent(&mut self, name: &str) -> Option<&mut Account> {
+        let is_parent = match &self.data {
+            Leaf { .. } => false,
+            Branch { children, .. } => children.iter().any(|child| child.account.name == name)
+        };
+        if is_parent {
+            return Some(self);
+        }
+        match &mut self.data {
+            Leaf { .. } => None,
+            Branch { children, .. } => children.iter_mut().find_map(|child| child.account.find_parent(name))
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<&Account> {
+        if self.name == name {
+            return Some(self);
+        }
+        match &self.data {
+            Leaf { .. } => None,
+            Branch { children, .. } => children.iter().find_map(|child| child.account.find(name))
+        }
+    }
+
+    /// Look up the balance of an account without needing mutable access to the tree
+    pub fn balance_of(&self, name: &str) -> Option<Money> {
+        self.find(name).map(Account::balance)
+    }
+
+    /// The full path to the named account, like root/Living/Food, or the first one found if the name is used more
+    /// than once
+    pub fn path_of(&self, name: &str) -> Option<String> {
+        self.paths().into_iter()
+            .find(|(path, _)| path.last() == Some(&name))
+            .map(|(path, _)| path.join("/"))
+    }
+
+    /// The path to every leaf from this account, like root/Living/Food, in display order
+    pub fn leaf_paths(&self) -> Vec<String> {
+        self.paths().into_iter()
+            .filter(|(_, account)| account.is_leaf())
+            .map(|(path, _)| path.join("/"))
+            .collect()
+    }
+
+    /// The full path, like root/Living/Food, to the account that actions naming it act on
+    pub fn resolve_path(&self, name: &str) -> Result<String, BudgetError> {
+        self.resolve(name).map(|path| path.join("/"))
+    }
+
+    /// The inflow the named account gets from its parent, searching in the same order as balance_of
+    pub fn inflow_of(&self, name: &str) -> Option<&Inflow> {
+        self.children().iter().find_map(|child| if child.account.name == name {
+            Some(&child.inflow)
+        } else {
+            child.account.inflow_of(name)
+        })
+    }
+
+    /// Find an account by the names along the way from this account to it, starting with this account's own name
+    pub fn find_by_path(&mut self, path: &[&str]) -> Option<&mut Account> {
+        match path {
+            [name] if *name == self.name => Some(self),
+            [name, rest @ ..] if *name == self.name => match &mut self.data {
+                Leaf { .. } => None,
+                Branch { children, .. } => children.iter_mut()
+                    .find(|child| child.account.name == rest[0])
+                    .and_then(|child| child.account.find_by_path(rest))
+            }
+            _ => None
+        }
+    }
+
+    // Every account in the tree along with the names on the way to it, starting with this account's own, leaving out
+    // archived accounts like iter does
+    fn paths(&self) -> Vec<(Vec<&str>, &Account)> {
+        self.paths_with(false)
+    }
+
+    // Every account and the names on the way to it, including archived ones when asked for
+    fn paths_with(&self, archived: bool) -> Vec<(Vec<&str>, &Account)> {
+        let mut paths = vec![(vec![self.name.as_str()], self)];
+        for child in self.children().iter().filter(|child| archived || child.active()) {
+            for (mut path, account) in child.account.paths_with(archived) {
+                path.insert(0, self.name.as_str());
+                paths.push((path, account));
+            }
+        }
+        paths
+    }
+
+    // Accounts can be named either by a bare name, which has to be unique, or by a slash-delimited path like
+    // root/Living/Food; either way this finds the full path to the account, even if it's archived
+    fn resolve(&self, name: &str) -> Result<Vec<String>, BudgetError> {
+        let mut matches = self.paths_with(true).into_iter()
+            .map(|(path, _)| path)
+            .filter(|path| if name.contains('/') { path.join("/") == name } else { path.last() == Some(&name) });
+        match (matches.next(), matches.next()) {
+            (Some(path), None) => Ok(path.into_iter().map(str::to_owned).collect()),
+            (Some(_), Some(_)) => Err(BudgetError::AmbiguousName(name.to_owned())),
+            (None, _) => Err(BudgetError::AccountNotFound(name.to_owned()))
+        }
+    }
+
+    // Resolve an account that the action can't be applied to if it's the root
+    fn resolve_below_root(&self, name: &str, action: &'static str) -> Result<Vec<String>, BudgetError> {
+        let path = self.resolve(name).map_err(|error| error.when_not_found(|name| BudgetError::NotFoundTo { name, action }))?;
+        if path.len() == 1 {
+            return Err(BudgetError::RootAccount { action, name: name.to_owned() });
+        }
+        Ok(path)
+    }
+
+    fn find_account(&mut self, name: &str) -> Result<&mut Account, BudgetError> {
+        let path = self.resolve(name)?;
+        Ok(self.at(&path))
+    }
+
+    // Resolve an account that's about to be deposited into, which it can't be if it or anything above it is archived
+    fn resolve_deposit(&mut self, name: &str) -> Result<Vec<String>, BudgetError> {
+        let path = self.resolve(name)?;
+        if (1..=path.len()).any(|length| self.at(&path[..length]).archived) {
+            return Err(BudgetError::Archived(name.to_owned()));
+        }
+        Ok(path)
+    }
+
+    // The account at a path that's already been resolved
+    fn at(&mut self, path: &[String]) -> &mut Account {
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        self.find_by_path(&path).expect("resolved paths lead to an account")
+    }
+
+    // The entry for a resolved account below the root in its parent
+    fn entry_at(&mut self, path: &[String]) -> &mut BranchEntry {
+        let (name, parent) = path.split_last().expect("paths are never empty");
+        match &mut self.at(parent).data {
+            Leaf { .. } => unreachable!("a leaf is never a parent"),
+            Branch { children, .. } => children.iter_mut()
+                .find(|child| child.account.name == *name)
+                .expect("resolved paths lead to an account")
+        }
+    }
+
+    // Take a resolved account below the root out of its parent
+    fn detach(&mut self, path: &[String]) -> BranchEntry {
+        let (name, parent) = path.split_last().expect("paths are never empty");
+        match &mut self.at(parent).data {
+            Leaf { .. } => unreachable!("a leaf is never a parent"),
+            Branch { children, .. } => {
+                let index = children.iter()
+                    .position(|child| child.account.name == *name)
+                    .expect("resolved paths lead to an account");
+                children.remove(index)
+            }
+        }
+    }
+
+    /// Add an account directly under this one
+    ///
+    /// Names only have to be unique among siblings, so the same name can appear under different
+    /// parents and be told apart by path.
+    pub fn add_child(&mut self, account: Account, inflow: Inflow) -> Result<(), BudgetError> {
+        self.add_entry(BranchEntry { account, inflow, priority: 0 })
+    }
+
+    fn add_entry(&mut self, mut entry: BranchEntry) -> Result<(), BudgetError> {
+        self.check_child(&entry.account.name, &entry.inflow)?;
+        match &mut self.data {
+            Leaf { .. } => Err(BudgetError::AddChildToLeaf),
+            Branch { children, unit, .. } => {
+                // The whole tree splits deposits into the same unit
+                entry.account.set_unit(*unit);
+                children.push(entry);
+                Ok(())
+            }
+        }
+    }
+
+    // Put the account's subtree back together one child at a time, so children that came in already attached get the
+    // same checks as ones added through add_child
+    fn rebuilt(mut self) -> Result<Account, BudgetError> {
+        let children = match &mut self.data {
+            Leaf { .. } => return Ok(self),
+            Branch { children, .. } => mem::take(children)
+        };
+        for entry in children {
+            entry.inflow.validate()?;
+            let account = entry.account.rebuilt()?;
+            self.add_entry(BranchEntry { account, ..entry })?;
+        }
+        Ok(self)
+    }
+
+    fn check_child(&self, name: &str, inflow: &Inflow) -> Result<(), BudgetError> {
+        let children = match &self.data {
+            Leaf { .. } => return Err(BudgetError::AddChildToLeaf),
+            Branch { children, .. } => children
+        };
+        if children.iter().any(|child| child.account.name == name) {
+            return Err(BudgetError::DuplicateName(name.to_owned()));
+        }
+        self.check_percent(name, inflow)
+    }
+
+    // Make sure the named child taking this inflow wouldn't push its siblings' percents past 100
+    fn check_percent(&self, name: &str, inflow: &Inflow) -> Result<(), BudgetError> {
+        if let Percent(percent) = inflow {
+            let total = self.children().iter()
+                .filter(|child| child.account.name != name)
+                .filter_map(|child| match child.inflow {
+                    Percent(percent) => Some(percent),
+                    _ => None
+                })
+                .sum::<f64>() + percent;
+            if total > 100.0 {
+                return Err(BudgetError::PercentOver100(total));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply each `date,account,amount` row as a deposit, or a withdrawal if the amount is negative
+    ///
+    /// Returns how many transactions were applied. A header row, if present, is skipped. If any row can't be read or
+    /// applied, none of them are.
+    pub fn import_transactions_csv<R: Read>(&mut self, input: R) -> Result<usize, BudgetError> {
+        // Apply the rows to a copy so a bad row partway through leaves this tree untouched
+        let mut staged = self.clone();
+        let mut count = 0;
+        for (index, line) in BufReader::new(input).lines().enumerate() {
+            let num = index + 1;
+            let malformed = |reason: String| BudgetError::MalformedCsv { line: num, reason };
+            let line = line.map_err(|e| malformed(e.to_string()))?;
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            match fields.as_slice() {
+                [""] => continue,
+                ["date", "account", "amount"] if num == 1 => continue,
+                [date, account, amount] => {
+                    let date = parse_date(num, &mut once(*date)).map_err(malformed)?;
+                    let amount = amount.parse::<Money>().map_err(malformed)?;
+                    let account = account.to_string();
+                    if amount < Money::ZERO {
+                        staged.apply(Withdraw { account, amount: -amount, date })?;
+                    } else {
+                        staged.apply(Deposit { account: Some(account), amount, date })?;
+                    }
+                    count += 1;
+                }
+                _ => return Err(malformed(format!("expected 3 fields, found {}", fields.len())))
+            }
+        }
+        *self = staged;
+        Ok(count)
+    }
+
+    /// Write a row for every leaf with its path, balance, and max, leaving the max blank if it's unlimited
+    pub fn export_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "path,balance,max")?;
+        self.export_csv_rows(out, "")
+    }
+
+    fn export_csv_rows<W: Write>(&self, out: &mut W, parent: &str) -> io::Result<()> {
+        let path = format!("{}{}", parent, self.name);
+        match &self.data {
+            Leaf { balance, max, .. } if max.is_unlimited() => writeln!(out, "{},{},", path, balance),
+            Leaf { balance, max, .. } => writeln!(out, "{},{},{}", path, balance, max),
+            Branch { children, .. } => {
+                let parent = format!("{}/", path);
+                for child in children {
+                    child.account.export_csv_rows(out, &parent)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Call the visitor for each account in the tree, in the order they're displayed, skipping archived ones
+    pub fn walk<V: Visitor>(&self, visitor: &mut V) {
+        self.walk_level(visitor, 0, None);
+    }
+
+    fn walk_level<V: Visitor>(&self, visitor: &mut V, depth: u32, inflow: Option<&Inflow>) {
+        match &self.data {
+            Leaf { .. } => visitor.visit_leaf(depth, self, inflow),
+            Branch { children, .. } => {
+                visitor.enter_branch(depth, self, inflow);
+                for child in children.iter().filter(|child| child.active()) {
+                    child.account.walk_level(visitor, depth + 1, Some(&child.inflow));
+                }
+                visitor.exit_branch(depth, self);
+            }
+        }
+    }
+
+    /// The same view of the tree as Display, with amounts written in the given format
+    pub fn display_with(&self, format: &CurrencyFormat) -> String {
+        let mut out = String::new();
+        self.print_level(&mut out, 0, Flex(1.0), format).expect("writing to a String can't fail");
+        out
+    }
+
+    fn print_level(&self, f: &mut impl fmt::Write, level: u32, inflow: Inflow, format: &CurrencyFormat) -> fmt::Result {
+        write!(f, "{:?}:\t", inflow)?;
+        for _ in 0..level {
+            write!(f, "  ")?;
+        }
+        write!(f, "{}: {}", self.name, format.format_amount(self.balance()))?;
+        if let Leaf { currency: Some(currency), .. } = &self.data {
+            write!(f, " {}", currency)?;
+        }
+        if let Some(created) = self.created {
+            write!(f, " (since {})", created)?;
+        }
+        if let Some(note) = &self.note {
+            write!(f, " - {}", note)?;
+        }
+        writeln!(f)?;
+        match &self.data {
+            Leaf {..}  => Ok(()),
+            Branch { children, .. } => {
+                for child in children.iter().filter(|child| child.active()) {
+                    child.account.print_level(f, level + 1, child.inflow.clone(), format)?
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The total weight of the flex inflows directly under this account
+    pub fn total_flex_weight(&self) -> f64 {
+        self.children().iter()
+            .filter_map(|child| match child.inflow {
+                Flex(weight) => Some(weight),
+                _ => None
+            })
+            .sum()
+    }
+
+    /// Find branches whose deposits will fall through to being split by room instead of through inflows, and fixed
+    /// inflows asking for more than their accounts can hold
+    pub fn funding_warnings(&self) -> Vec<FundingWarning> {
+        self.iter()
+            .flat_map(|account| {
+                let children = account.children();
+                let unfunded = !children.is_empty() && children.iter().all(|child| match child.inflow {
+                    Fixed(amount) => amount <= Money::ZERO,
+                    Flex(weight) => weight <= 0.0,
+                    Percent(_) => true,
+                    FillToMax => false
+                });
+                let over_max = children.iter()
+                    .filter(|child| child.active())
+                    .filter_map(|child| match child.inflow {
+                        Fixed(fixed) if fixed > child.account.max() => Some(FundingWarning::FixedOverMax {
+                            name: child.account.name.clone(),
+                            fixed,
+                            max: child.account.max()
+                        }),
+                        _ => None
+                    });
+                once(FundingWarning::UnfundedBranch(account.name.clone()))
+                    .filter(move |_| unfunded)
+                    .chain(over_max)
+            })
+            .collect()
+    }
+
+    /// Find overdrawn leaves, full leaves, and overfull branches, in the same order as iter
+    pub fn alerts(&self) -> Vec<Alert> {
+        self.paths().into_iter()
+            .filter_map(|(path, account)| {
+                let (balance, max) = (account.balance(), account.max());
+                let reason = match account.data {
+                    Leaf { .. } if balance < Money::ZERO => AlertReason::Negative(balance),
+                    // An empty leaf with no max isn't worth mentioning
+                    Leaf { .. } if balance >= max && balance > Money::ZERO => AlertReason::AtMax { balance, max },
+                    Branch { .. } if balance > max => AlertReason::OverMax { balance, max },
+                    _ => return None
+                };
+                Some(Alert { path: path.join("/"), reason })
+            })
+            .collect()
+    }
+
+    /// Look for corruption in the tree, describing every problem found
+    ///
+    /// Balances past a leaf's max aren't problems, since deposits that can't be placed anywhere else overflow into
+    /// the children.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        self.collect_problems(&mut problems);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("\n"))
+        }
+    }
+
+    fn collect_problems(&self, problems: &mut Vec<String>) {
+        match &self.data {
+            Leaf { balance, max, allow_negative, .. } => {
+                if balance.is_unlimited() || *balance == -Money::UNLIMITED {
+                    problems.push(format!("{} has a balance of {}, which has overflowed", self.name, balance));
+                }
+                if *balance < Money::ZERO && !allow_negative {
+                    problems.push(format!("{} has a negative balance of {} but doesn't allow one", self.name, balance));
+                }
+                if *max < Money::ZERO {
+                    problems.push(format!("{} has a negative max of {}", self.name, max));
+                }
+            }
+            Branch { children, .. } => {
+                for (index, child) in children.iter().enumerate() {
+                    if children[..index].iter().any(|other| other.account.name == child.account.name) {
+                        problems.push(format!("{} has more than one child named {}", self.name, child.account.name));
+                    }
+                    if let Err(e) = child.inflow.validate() {
+                        problems.push(format!("{} has an invalid inflow: {}", child.account.name, e));
+                    }
+                }
+                let percent: f64 = children.iter()
+                    .filter_map(|child| match child.inflow {
+                        Percent(percent) => Some(percent),
+                        _ => None
+                    })
+                    .sum();
+                if percent > 100.0 {
+                    problems.push(format!("The children of {} take {}% of each deposit", self.name, percent));
+                }
+                children.iter().for_each(|child| child.account.collect_problems(problems));
+            }
+        }
+    }
+
+    /// A status view of the tree with each account's balance, max, how close it is to the max, and inflow
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        self.report_level(&mut out, 0, None);
+        out
+    }
+
+    fn report_level(&self, out: &mut String, level: usize, inflow: Option<&Inflow>) {
+        let max = self.max();
+        out.push_str(&"  ".repeat(level));
+        out.push_str(&format!("{}: {} of {}", self.name, self.balance(), max));
+        if !max.is_unlimited() && max > Money::ZERO {
+            let percent = self.balance().cents() as f64 / max.cents() as f64 * 100.0;
+            out.push_str(&format!(" ({:.0}%)", percent));
+        }
+        if let Some(inflow) = inflow {
+            out.push_str(&format!(" [{:?}]", inflow));
+        }
+        out.push('\n');
+        for child in self.children().iter().filter(|child| child.active()) {
+            child.account.report_level(out, level + 1, Some(&child.inflow));
+        }
+    }
+
+    /// List the leaves that changed since an earlier snapshot of this tree, matching them up by path
+    ///
+    /// Changed and added leaves come first, in the order they're in now, followed by any that were removed.
+    pub fn changes_since(&self, before: &Account) -> Vec<AccountDiff> {
+        let leaf_paths = |account: &Account| -> Vec<(String, Money)> {
+            account.paths().into_iter()
+                .filter(|(_, account)| account.is_leaf())
+                .map(|(path, account)| (path.join("/"), account.balance()))
+                .collect()
+        };
+        let (before, after) = (leaf_paths(before), leaf_paths(self));
+        let find = |leaves: &[(String, Money)], path: &str| leaves.iter()
+            .find(|(other, _)| other == path)
+            .map(|(_, balance)| *balance);
+        let current = after.iter().filter_map(|(path, balance)| match find(&before, path) {
+            None => Some(AccountDiff::Added { path: path.clone(), balance: *balance }),
+            Some(old) if old != *balance => Some(AccountDiff::Changed { path: path.clone(), before: old, after: *balance }),
+            Some(_) => None
+        });
+        let removed = before.iter()
+            .filter(|(path, _)| find(&after, path).is_none())
+            .map(|(path, balance)| AccountDiff::Removed { path: path.clone(), balance: *balance });
+        current.chain(removed).collect()
+    }
+
+    /// Whether the two trees have the same accounts in the same order, with the same kinds of inflows
+    ///
+    /// Balances, maxes, and inflow amounts can all differ, so a fixed inflow of 100 matches one of 50.
+    pub fn same_structure(&self, other: &Account) -> bool {
+        self.name == other.name
+            && self.is_leaf() == other.is_leaf()
+            && self.children().len() == other.children().len()
+            && self.children().iter().zip(other.children()).all(|(child, other)| {
+                mem::discriminant(&child.inflow) == mem::discriminant(&other.inflow) && child.account.same_structure(&other.account)
+            })
+    }
+
+    pub fn diff(&self, other: &Account) -> Result<Account, BudgetError> {
+        let data = match (&self.data, &other.data) {
+            (Leaf { balance: end, max, allow_negative, rollover, goal, currency }, Leaf { balance: start, .. }) => {
+                Leaf {
+                    balance: *end - *start,
+                    max: *max,
+                    allow_negative: *allow_negative,
+                    rollover: *rollover,
+                    goal: *goal,
+                    currency: currency.clone()
+                }
+            }
+            (Branch { children: start, overflow, unit }, Branch { children: end, .. }) => {
+                let mut children = Vec::new();
+                for start_child in start {
+                    for end_child in end {
+                        let end_child = &end_child.account;
+                        let start_child = &start_child.account;
+                        if end_child.name == start_child.name {
+                            children.push(BranchEntry {
+                                account: end_child.diff(start_child)?,
+                                inflow: Inflow::Fixed(Money::ZERO),
+                                priority: 0
+                            });
+                        }
+                    }
+                }
+                for end_child in end {
+                    let end_child = &end_child.account;
+                    if children.iter().filter(|child| child.account.name == end_child.name).count() == 0 {
+                            children.push(BranchEntry {
+                                account: end_child.clone(),
+                                inflow: Inflow::Fixed(Money::ZERO),
+                                priority: 0
+                            });
+                    }
+                }
+
+                Branch { children, overflow: overflow.clone(), unit: *unit }
+            }
+            (_, _) => return Err(BudgetError::DiffMismatch)
+        };
+
+        Ok(Account {
+            name: self.name.clone(),
+            data,
+            note: self.note.clone(),
+            created: self.created,
+            last_activity: self.last_activity,
+            frozen: self.frozen,
+            archived: self.archived
+        })
+    }
+}
+
+impl Default for Account {
+    fn default() -> Account {
+        Account::new_root()
+    }
+}
+
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.print_level(f, 0, Inflow::Flex(1.0), &CurrencyFormat::default())
+    }
+}
+
+impl AccountBuilder {
+    pub fn new(name: &str) -> AccountBuilder {
+        AccountBuilder {
+            account: Account { name: name.to_owned(), ..Account::new_root() },
+            error: None
+        }
+    }
+
+    /// Add an empty leaf
+    pub fn leaf(self, name: &str, inflow: Inflow, max: Money) -> AccountBuilder {
+        let data = Leaf { balance: Money::ZERO, max, allow_negative: false, rollover: Rollover::Keep, goal: None, currency: None };
+        self.child(Account { name: name.to_owned(), data, note: None, created: None, last_activity: None, frozen: false, archived: false }, inflow)
+    }
+
+    /// Add a branch, with its own children added by the closure
+    pub fn branch(self, name: &str, inflow: Inflow, build: impl FnOnce(AccountBuilder) -> AccountBuilder) -> AccountBuilder {
+        let branch = build(AccountBuilder::new(name));
+        match branch.error {
+            Some(error) => AccountBuilder { error: self.error.or(Some(error)), ..self },
+            None => self.child(branch.account, inflow)
+        }
+    }
+
+    /// Send what's left of deposits after inflows to the named child or descendant
+    pub fn overflow(mut self, name: &str) -> AccountBuilder {
+        if let Branch { overflow, .. } = &mut self.account.data {
+            *overflow = Some(name.to_owned());
+        }
+        self
+    }
+
+    fn child(mut self, account: Account, inflow: Inflow) -> AccountBuilder {
+        if self.error.is_none() {
+            self.error = inflow.validate()
+                .and_then(|_| self.account.add_child(account, inflow))
+                .err();
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<Account, BudgetError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.account)
+        }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Account;
+
+    fn next(&mut self) -> Option<&'a Account> {
+        let account = self.stack.pop()?;
+        if let Branch { children, .. } = &account.data {
+            let archived = self.archived;
+            self.stack.extend(children.iter().rev().filter(|child| archived || child.active()).map(|child| &child.account));
+        }
+        Some(account)
+    }
+}
+
+impl BranchEntry {
+    // Whether the parent counts this child in its totals and deposits
+    fn active(&self) -> bool {
+        !self.account.archived
+    }
+
+    pub fn account(&self) -> &Account {
+        &self.account
+    }
+
+    pub fn inflow(&self) -> &Inflow {
+        &self.inflow
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    // Returns whatever is left after funding the children, with percents taken out of the original incoming amount
+    fn fund_tier(tier: &mut [&mut BranchEntry], amount: Money, incoming: Money, capped: bool, unit: Money) -> Money {
+        // Make percent deposits
+        let amount = tier.iter_mut()
+            .fold(amount, |amount, child| child.make_percent_deposit(amount, incoming, capped, unit));
+        // Make fixed deposits
+        let amount = tier.iter_mut()
+            .fold(amount, |amount, child| child.make_fixed_deposit(amount, capped));
+        // Make flex deposits
+        BranchEntry::make_flex_deposits(tier, amount, capped, unit)
+    }
+
+    // Deposit into the child, returning how much it actually took
+    fn receive(&mut self, amount: Money, capped: bool) -> Money {
+        if capped {
+            amount - self.account.deposit_capped(amount)
+        } else {
+            self.account.deposit(amount);
+            amount
+        }
+    }
+
+    // Splits the amount between the flex children by weight in a single pass. Children whose share would take them
+    // to their max are filled first and the rest is split again without them, so there's at most one extra pass per
+    // child. The rest is handed out in whole units by split_by_weight.
+    //
+    // A capped child that hands some of its deposit back is left out of later passes, since it can't take any more.
+    fn make_flex_deposits(tier: &mut [&mut BranchEntry], mut amount: Money, capped: bool, unit: Money) -> Money {
+        let mut refused: Vec<usize> = Vec::new();
+        loop {
+            let open: Vec<usize> = (0..tier.len())
+                .filter(|&index| tier[index].get_flex() > 0.0 && !refused.contains(&index))
+                .collect();
+            if open.is_empty() || amount <= Money::ZERO {
+                return amount;
+            }
+            let weights: Vec<f64> = open.iter().map(|&index| tier[index].get_flex()).collect();
+            let total_flex: f64 = weights.iter().sum();
+            let units = amount.cents() / unit.cents();
+            let shares: Vec<f64> = weights.iter().map(|weight| units as f64 * weight / total_flex).collect();
+            let filled: Vec<usize> = open.iter().zip(&shares)
+                .filter(|(&index, share)| Money::from_cents(share.ceil() as i64 * unit.cents()) >= tier[index].until_max())
+                .map(|(&index, _)| index)
+                .collect();
+            if !filled.is_empty() {
+                for index in filled {
+                    let take = tier[index].until_max().min(amount);
+                    let taken = tier[index].receive(take, capped);
+                    if taken < take {
+                        refused.push(index);
+                    }
+                    amount -= taken;
+                }
+                continue;
+            }
+            for (&index, share) in open.iter().zip(split_by_weight(amount, &weights, unit)) {
+                amount -= tier[index].receive(share, capped);
+            }
+            return amount;
+        }
+    }
+
+    fn until_max(&self) -> Money {
+        self.account.remaining_to_max()
     }
 
     fn at_max(&self) -> bool {
-        self.until_max() <= 0.0
+        self.account.is_full()
+    }
+
+    fn get_flex(&self) -> f64 {
+        match self.inflow {
+            Fixed(_) | Percent(_) | FillToMax => 0.0,
+            Flex(_) if self.at_max() => 0.0,
+            Flex(x) => x
+        }
+    }
+
+    // Recurring debits are charged once a month by charge_debit, not on every deposit
+    fn make_fixed_deposit(&mut self, available: Money, capped: bool) -> Money {
+        match self.inflow {
+            Fixed(take) if take <= Money::ZERO => available,
+            Fixed(take) => {
+                let take = take.min(self.until_max().max(Money::ZERO)).min(available);
+                available - self.receive(take, capped)
+            }
+            // An unlimited max is never reached, so this takes everything available
+            FillToMax => {
+                let take = self.until_max().max(Money::ZERO).min(available);
+                available - self.receive(take, capped)
+            }
+            _ => available
+        }
+    }
+
+    // A negative fixed inflow is a recurring debit, like a subscription, which never goes below zero unless allowed
+    //
+    // Frozen accounts skip their debits.
+    fn charge_debit(&mut self) {
+        match self.inflow {
+            Fixed(take) if take < Money::ZERO && !self.account.frozen => {
+                let debit = match self.account.data {
+                    Leaf { allow_negative: true, .. } => -take,
+                    _ => (-take).min(self.account.withdrawable().max(Money::ZERO))
+                };
+                // Inflow validation keeps out debits too large to withdraw, but a tree loaded from a file skips it
+                let _ = self.account.withdraw(debit);
+            }
+            _ => ()
+        }
+    }
+
+    // Percent shares are rounded down to whole units, which leaves the rest for the children after them
+    fn make_percent_deposit(&mut self, available: Money, incoming: Money, capped: bool, unit: Money) -> Money {
+        match self.inflow {
+            Percent(percent) => {
+                let share = (incoming.cents() as f64 * percent / 100.0).round() as i64;
+                // An account already past its max has no room, rather than negative room to hand some back from
+                let take = Money::from_cents(share - share % unit.cents())
+                    .min(self.until_max().max(Money::ZERO))
+                    .min(available);
+                available - self.receive(take, capped)
+            }
+            _ => available
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(amount: &str) -> Money {
+        amount.parse().unwrap()
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    // root > Rent (fixed 100, max 100), Living (flex 1) > Food (flex 1, max 200), Fun (flex 1)
+    fn sample() -> Account {
+        AccountBuilder::new("root")
+            .leaf("Rent", Fixed(money("100")), money("100"))
+            .branch("Living", Flex(1.0), |living| living
+                .leaf("Food", Flex(1.0), money("200"))
+                .leaf("Fun", Flex(1.0), Money::UNLIMITED))
+            .build()
+            .unwrap()
+    }
+
+    fn deposit(root: &mut Account, account: Option<&str>, amount: &str) {
+        root.apply(Deposit { account: account.map(str::to_owned), amount: money(amount), date: date(2020, 1, 1) }).unwrap();
+    }
+
+    #[test]
+    fn tree_round_trips_through_json() {
+        let mut root = sample();
+        deposit(&mut root, None, "250");
+        let json = serde_json::to_string(&root).unwrap();
+        assert!(json.contains(r#""type":"Branch""#));
+        assert!(json.contains(r#""type":"Leaf""#));
+        assert!(json.contains(r#""type":"Fixed""#));
+        let loaded: Account = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, root);
+        assert_eq!(loaded.balance_of("Food"), Some(money("75")));
+    }
+
+    #[test]
+    fn save_and_load_a_three_level_tree() {
+        let mut root = sample();
+        deposit(&mut root, None, "321.09");
+        let path = std::env::temp_dir().join(format!("budget-save-test-{}.json", std::process::id()));
+        root.save_to_file(&path).unwrap();
+        let loaded = Account::load_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, root);
+        assert_eq!(loaded.balance_of("Fun"), root.balance_of("Fun"));
+    }
+
+    #[test]
+    fn loading_a_missing_or_malformed_file_is_an_error() {
+        let missing = std::env::temp_dir().join("budget-test-file-that-does-not-exist.json");
+        assert!(Account::load_from_file(&missing).unwrap_err().starts_with("Could not open saved budget"));
+        let path = std::env::temp_dir().join(format!("budget-malformed-test-{}.json", std::process::id()));
+        std::fs::write(&path, "{ not json").unwrap();
+        let loaded = Account::load_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.unwrap_err().starts_with("Could not read saved budget"));
+    }
+
+    #[test]
+    fn display_writes_the_tree_into_the_formatter() {
+        let mut root = sample();
+        deposit(&mut root, None, "150");
+        let shown = format!("{}", root);
+        assert_eq!(shown, "\
+Flex(1.0):\troot: 150.00
+Fixed(100.00):\t  Rent: 100.00
+Flex(1.0):\t  Living: 50.00
+Flex(1.0):\t    Food: 25.00
+Flex(1.0):\t    Fun: 25.00
+");
+    }
+
+    #[test]
+    fn deposits_split_into_whole_cents_without_residue() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("A", Flex(1.0), Money::UNLIMITED)
+            .leaf("B", Flex(1.0), Money::UNLIMITED)
+            .leaf("C", Flex(1.0), Money::UNLIMITED)
+            .build()
+            .unwrap();
+        for _ in 0..10 {
+            deposit(&mut root, None, "0.10");
+        }
+        deposit(&mut root, None, "100");
+        let leaves: Money = root.iter_leaves().map(Account::balance).sum();
+        assert_eq!(leaves, money("101"));
+        assert_eq!(root.balance(), money("101"));
+        assert_eq!(root.unallocated(), Money::ZERO);
+    }
+
+    #[test]
+    fn remove_takes_a_leaf_out_of_its_parent() {
+        let mut root = sample();
+        root.apply(Remove { name: "Fun".to_owned() }).unwrap();
+        assert!(root.find_child("Fun").is_none());
+        assert_eq!(root.children()[1].account().children().len(), 1);
+    }
+
+    #[test]
+    fn removing_a_leaf_puts_its_balance_back_into_the_tree() {
+        let mut root = sample();
+        deposit(&mut root, None, "300");
+        let total = root.balance();
+        root.apply(Remove { name: "Fun".to_owned() }).unwrap();
+        assert_eq!(root.balance(), total);
+    }
+
+    #[test]
+    fn remove_refuses_the_root_missing_accounts_and_branches_with_children() {
+        let mut root = sample();
+        assert_eq!(root.apply(Remove { name: "root".to_owned() }), Err(BudgetError::RootAccount { action: "remove", name: "root".to_owned() }));
+        assert_eq!(root.apply(Remove { name: "Nope".to_owned() }), Err(BudgetError::NotFoundTo { name: "Nope".to_owned(), action: "remove" }));
+        assert_eq!(root.apply(Remove { name: "Living".to_owned() }), Err(BudgetError::HasChildren("Living".to_owned())));
+    }
+
+    #[test]
+    fn rename_changes_which_name_resolves() {
+        let mut root = sample();
+        deposit(&mut root, None, "150");
+        root.apply(Rename { name: "Food".to_owned(), new_name: "Groceries".to_owned() }).unwrap();
+        assert_eq!(root.balance_of("Food"), None);
+        assert_eq!(root.balance_of("Groceries"), Some(money("25")));
+    }
+
+    #[test]
+    fn rename_refuses_a_sibling_name() {
+        let mut root = sample();
+        let rename = Rename { name: "Food".to_owned(), new_name: "Fun".to_owned() };
+        let taken = BudgetError::RenameTaken { name: "Food".to_owned(), new_name: "Fun".to_owned(), parent: "Living".to_owned() };
+        assert_eq!(root.apply(rename), Err(taken));
+        assert!(root.find_child("Food").is_some());
+    }
+
+    #[test]
+    fn move_keeps_the_subtree_and_the_total() {
+        let mut root = sample();
+        root.apply(New {
+            name: "Savings".to_owned(),
+            inflow: Flex(1.0),
+            priority: 0,
+            parent: "root".to_owned(),
+            data: Branch { children: Vec::new(), overflow: None, unit: Money::CENT },
+            note: None,
+            created: None
+        }).unwrap();
+        deposit(&mut root, None, "300");
+        let total = root.balance();
+        root.apply(Move { name: "Living".to_owned(), new_parent: "Savings".to_owned() }).unwrap();
+        assert_eq!(root.balance(), total);
+        assert_eq!(root.path_of("Food"), Some("root/Savings/Living/Food".to_owned()));
+        assert_eq!(root.inflow_of("Living"), Some(&Flex(1.0)));
+        assert_eq!(root.find_child("Living").unwrap().children().len(), 2);
+    }
+
+    #[test]
+    fn move_refuses_cycles_and_the_root() {
+        let mut root = sample();
+        let into_child = Move { name: "Living".to_owned(), new_parent: "Food".to_owned() };
+        assert_eq!(root.apply(into_child), Err(BudgetError::MoveIntoSelf { name: "Living".to_owned(), new_parent: "Food".to_owned() }));
+        let into_self = Move { name: "Living".to_owned(), new_parent: "Living".to_owned() };
+        assert!(matches!(root.apply(into_self), Err(BudgetError::MoveIntoSelf { .. })));
+        let root_move = Move { name: "root".to_owned(), new_parent: "Living".to_owned() };
+        assert_eq!(root.apply(root_move), Err(BudgetError::RootAccount { action: "move", name: "root".to_owned() }));
+        let missing = Move { name: "Nope".to_owned(), new_parent: "Living".to_owned() };
+        assert_eq!(root.apply(missing), Err(BudgetError::NotFoundTo { name: "Nope".to_owned(), action: "move" }));
+    }
+
+    // root > Home (flex 1) > Food (flex 1), Work (flex 1) > Food (flex 1)
+    fn two_foods() -> Account {
+        AccountBuilder::new("root")
+            .branch("Home", Flex(1.0), |home| home.leaf("Food", Flex(1.0), Money::UNLIMITED))
+            .branch("Work", Flex(1.0), |work| work.leaf("Food", Flex(1.0), Money::UNLIMITED))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn paths_tell_apart_accounts_with_the_same_name() {
+        let mut root = two_foods();
+        deposit(&mut root, Some("root/Work/Food"), "40");
+        root.apply(Withdraw { account: "root/Work/Food".to_owned(), amount: money("15"), date: date(2020, 1, 2) }).unwrap();
+        assert_eq!(root.find_by_path(&["root", "Home", "Food"]).unwrap().balance(), Money::ZERO);
+        assert_eq!(root.find_by_path(&["root", "Work", "Food"]).unwrap().balance(), money("25"));
+        assert!(root.find_by_path(&["root", "Food"]).is_none());
+    }
+
+    #[test]
+    fn withdrawing_from_a_branch_draws_from_its_leaves_in_order() {
+        let mut root = sample();
+        deposit(&mut root, None, "200");
+        root.apply(Withdraw { account: "Living".to_owned(), amount: money("70"), date: date(2020, 1, 2) }).unwrap();
+        assert_eq!(root.balance_of("Food"), Some(Money::ZERO));
+        assert_eq!(root.balance_of("Fun"), Some(money("30")));
+        assert_eq!(root.balance_of("Living"), Some(money("30")));
+    }
+
+    #[test]
+    fn withdrawing_more_than_a_branch_holds_names_the_shortfall() {
+        let mut root = sample();
+        deposit(&mut root, None, "200");
+        let withdraw = Withdraw { account: "Living".to_owned(), amount: money("120"), date: date(2020, 1, 2) };
+        let error = root.apply(withdraw).unwrap_err();
+        assert_eq!(error, BudgetError::InsufficientFunds { available: money("100"), requested: money("120") });
+        assert!(error.to_string().ends_with("20.00 short"));
+        assert_eq!(root.balance_of("Living"), Some(money("100")));
+    }
+
+    fn leaf(name: &str, balance: &str, max: Money) -> Account {
+        let data = Leaf { balance: money(balance), max, allow_negative: false, rollover: Rollover::Keep, goal: None, currency: None };
+        Account { name: name.to_owned(), data, note: None, created: None, last_activity: None, frozen: false, archived: false }
+    }
+
+    #[test]
+    fn withdrawing_past_zero_is_refused_by_default() {
+        let mut root = Account::new_root();
+        root.add_child(leaf("Food", "10", Money::UNLIMITED), Flex(1.0)).unwrap();
+        let withdraw = Withdraw { account: "Food".to_owned(), amount: money("15"), date: date(2020, 1, 1) };
+        assert_eq!(root.apply(withdraw), Err(BudgetError::InsufficientFunds { available: money("10"), requested: money("15") }));
+        assert_eq!(root.balance_of("Food"), Some(money("10")));
+    }
+
+    #[test]
+    fn leaves_that_allow_negatives_can_be_overdrawn() {
+        let mut card = leaf("Card", "10", Money::UNLIMITED);
+        if let Leaf { allow_negative, .. } = &mut card.data {
+            *allow_negative = true;
+        }
+        let mut root = Account::new_root();
+        root.add_child(card, Flex(1.0)).unwrap();
+        root.apply(Withdraw { account: "Card".to_owned(), amount: money("15"), date: date(2020, 1, 1) }).unwrap();
+        assert_eq!(root.balance_of("Card"), Some(money("-5")));
+    }
+
+    #[test]
+    fn new_month_resets_spending_and_keeps_sinking_funds() {
+        let mut spending = leaf("Spending", "0", money("50"));
+        if let Leaf { rollover, .. } = &mut spending.data {
+            *rollover = Rollover::Reset;
+        }
+        let mut root = Account::new_root();
+        root.add_child(spending, Fixed(money("50"))).unwrap();
+        root.add_child(leaf("Sinking", "0", Money::UNLIMITED), Flex(1.0)).unwrap();
+
+        root.new_month(money("80"));
+        root.apply(Withdraw { account: "Spending".to_owned(), amount: money("20"), date: date(2020, 1, 10) }).unwrap();
+        assert_eq!(root.balance_of("Spending"), Some(money("30")));
+        assert_eq!(root.balance_of("Sinking"), Some(money("30")));
+
+        root.new_month(money("80"));
+        assert_eq!(root.balance_of("Spending"), Some(money("50")));
+        assert_eq!(root.balance_of("Sinking"), Some(money("60")));
+    }
+
+    #[test]
+    fn percent_children_are_funded_first_from_the_incoming_amount() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("Rent", Fixed(money("50")), money("50"))
+            .leaf("Savings", Percent(20.0), Money::UNLIMITED)
+            .leaf("Fun", Flex(1.0), Money::UNLIMITED)
+            .build()
+            .unwrap();
+        deposit(&mut root, None, "60");
+        assert_eq!(root.balance_of("Savings"), Some(money("12")));
+        assert_eq!(root.balance_of("Rent"), Some(money("48")));
+        assert_eq!(root.balance_of("Fun"), Some(Money::ZERO));
+        deposit(&mut root, None, "100");
+        assert_eq!(root.balance_of("Savings"), Some(money("32")));
+        assert_eq!(root.balance_of("Rent"), Some(money("50")));
+        assert_eq!(root.balance_of("Fun"), Some(money("78")));
+    }
+
+    #[test]
+    fn percents_over_100_are_refused() {
+        let error = AccountBuilder::new("root")
+            .leaf("A", Percent(60.0), Money::UNLIMITED)
+            .leaf("B", Percent(50.0), Money::UNLIMITED)
+            .build()
+            .unwrap_err();
+        assert_eq!(error, BudgetError::PercentOver100(110.0));
+    }
+
+    #[test]
+    fn children_past_their_max_are_not_drawn_from_by_inflows() {
+        let mut root = Account::new_root();
+        root.add_child(leaf("Percent", "500", money("100")), Percent(50.0)).unwrap();
+        root.add_child(leaf("Fixed", "500", money("100")), Fixed(money("50"))).unwrap();
+        root.add_child(leaf("Flex", "0", Money::UNLIMITED), Flex(1.0)).unwrap();
+        deposit(&mut root, None, "100");
+        assert_eq!(root.balance_of("Percent"), Some(money("500")));
+        assert_eq!(root.balance_of("Fixed"), Some(money("500")));
+        assert_eq!(root.balance_of("Flex"), Some(money("100")));
+    }
+
+    #[test]
+    fn balance_of_reads_through_a_shared_reference() {
+        let mut root = sample();
+        deposit(&mut root, None, "150");
+        let shared = &root;
+        assert_eq!(shared.balance_of("Food"), Some(money("25")));
+        assert_eq!(shared.balance_of("Living"), Some(money("50")));
+        assert_eq!(shared.balance_of("Nope"), None);
+    }
+
+    #[test]
+    fn iter_visits_every_account_depth_first() {
+        let root = sample();
+        let names: Vec<&str> = root.iter().map(Account::name).collect();
+        assert_eq!(names, ["root", "Rent", "Living", "Food", "Fun"]);
+        let leaves: Vec<&str> = root.iter_leaves().map(Account::name).collect();
+        assert_eq!(leaves, ["Rent", "Food", "Fun"]);
+    }
+
+    #[test]
+    fn notes_and_created_dates_are_kept_and_shown() {
+        let mut root = Account::new_root();
+        root.apply(New {
+            name: "Car".to_owned(),
+            inflow: Flex(1.0),
+            priority: 0,
+            parent: "root".to_owned(),
+            data: leaf("Car", "0", Money::UNLIMITED).data,
+            note: Some("Insurance and repairs".to_owned()),
+            created: Some(date(2020, 4, 1))
+        }).unwrap();
+        let loaded: Account = serde_json::from_str(&serde_json::to_string(&root).unwrap()).unwrap();
+        let car = &loaded.children()[0].account;
+        assert_eq!(car.note.as_deref(), Some("Insurance and repairs"));
+        assert_eq!(car.created, Some(date(2020, 4, 1)));
+        assert!(loaded.to_string().contains("Car: 0.00 (since 2020-04-01) - Insurance and repairs"));
+    }
+
+    fn with_goal(balance: &str, target: &str, by: NaiveDate) -> Account {
+        let mut fund = leaf("Trip", balance, Money::UNLIMITED);
+        if let Leaf { goal, .. } = &mut fund.data {
+            *goal = Some(Goal { target: money(target), by });
+        }
+        fund
+    }
+
+    #[test]
+    fn required_monthly_splits_the_shortfall_over_the_months_left() {
+        let trip = with_goal("300", "600", date(2020, 4, 1));
+        assert_eq!(trip.required_monthly(date(2020, 1, 1)), Some(money("100")));
+        // Rounds up so the target is still reached
+        assert_eq!(with_goal("0", "100", date(2020, 4, 1)).required_monthly(date(2020, 1, 1)), Some(money("33.34")));
+    }
+
+    #[test]
+    fn required_monthly_once_met_or_past_the_deadline() {
+        assert_eq!(with_goal("600", "600", date(2020, 4, 1)).required_monthly(date(2020, 1, 1)), Some(Money::ZERO));
+        assert_eq!(with_goal("300", "600", date(2020, 4, 1)).required_monthly(date(2020, 5, 1)), None);
+        assert_eq!(leaf("Plain", "0", Money::UNLIMITED).required_monthly(date(2020, 1, 1)), None);
+    }
+
+    #[test]
+    fn changing_a_clone_leaves_the_original_alone() {
+        let original = sample();
+        let mut copy = original.clone();
+        assert_eq!(copy, original);
+        deposit(&mut copy, None, "10");
+        assert_ne!(copy, original);
+        assert_eq!(original.balance(), Money::ZERO);
+    }
+
+    #[test]
+    fn export_csv_writes_a_row_per_leaf() {
+        let mut root = sample();
+        deposit(&mut root, None, "150");
+        let mut out = Vec::new();
+        root.export_csv(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+path,balance,max
+root/Rent,100.00,100.00
+root/Living/Food,25.00,200.00
+root/Living/Fun,25.00,
+");
+    }
+
+    #[test]
+    fn import_applies_each_row() {
+        let mut root = sample();
+        let csv = "date,account,amount\n01/01/2020,root,150\n01/02/2020,Food,-5.50\n01/03/2020,Fun,10\n";
+        assert_eq!(root.import_transactions_csv(csv.as_bytes()), Ok(3));
+        assert_eq!(root.balance_of("Food"), Some(money("19.50")));
+        assert_eq!(root.balance_of("Fun"), Some(money("35")));
+    }
+
+    #[test]
+    fn import_reports_the_line_of_a_bad_row_and_applies_nothing() {
+        let mut root = sample();
+        let csv = "01/01/2020,root,150\n02/30/2020,Fun,5\n";
+        let error = root.import_transactions_csv(csv.as_bytes()).unwrap_err();
+        assert!(matches!(error, BudgetError::MalformedCsv { line: 2, .. }));
+        assert_eq!(root.balance(), Money::ZERO);
+
+        let csv = "01/01/2020,root,150\n01/02/2020,Fun\n";
+        let error = root.import_transactions_csv(csv.as_bytes()).unwrap_err();
+        assert_eq!(error, BudgetError::MalformedCsv { line: 2, reason: "expected 3 fields, found 2".to_owned() });
+
+        let csv = "01/01/2020,root,150\n01/02/2020,Nope,5\n";
+        let missing = BudgetError::TargetNotFound { name: "Nope".to_owned(), action: "deposit to" };
+        assert_eq!(root.import_transactions_csv(csv.as_bytes()), Err(missing));
+        assert_eq!(root.balance(), Money::ZERO);
+    }
+
+    #[test]
+    fn find_parent_returns_the_containing_branch() {
+        let mut root = sample();
+        assert_eq!(root.find_parent("Food").map(|parent| parent.name().to_owned()), Some("Living".to_owned()));
+        assert_eq!(root.find_parent("Rent").map(|parent| parent.name().to_owned()), Some("root".to_owned()));
+        assert!(root.find_parent("root").is_none());
+        assert!(root.find_parent("Nope").is_none());
     }
 
-    fn get_flex(&self) -> f64 {
-        match self.inflow {
-            Fixed(_) => 0.0,
-            Flex(_) if self.at_max() => 0.0,
-            Flex(x) => x
+    #[test]
+    fn siblings_cannot_share_a_name() {
+        let mut root = sample();
+        let new = |parent: &str| New {
+            name: "Food".to_owned(),
+            inflow: Flex(1.0),
+            priority: 0,
+            parent: parent.to_owned(),
+            data: leaf("Food", "0", Money::UNLIMITED).data,
+            note: None,
+            created: None
+        };
+        assert_eq!(root.apply(new("Living")), Err(BudgetError::DuplicateName("Food".to_owned())));
+        // Only siblings have to differ, since paths can tell the rest apart
+        root.apply(new("root")).unwrap();
+        assert_eq!(root.path_of("Food"), Some("root/Living/Food".to_owned()));
+    }
+
+    #[test]
+    fn unallocated_is_what_a_branch_holds_past_its_leaves_maxes() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("A", Flex(1.0), money("30"))
+            .leaf("B", Flex(1.0), money("20"))
+            .build()
+            .unwrap();
+        deposit(&mut root, None, "40");
+        assert_eq!(root.unallocated(), Money::ZERO);
+        deposit(&mut root, None, "25");
+        assert_eq!(root.unallocated(), money("15"));
+        assert_eq!(root.balance(), money("65"));
+    }
+
+    #[test]
+    fn remaining_to_max_and_is_full() {
+        let partial = leaf("Food", "40", money("100"));
+        assert_eq!(partial.max(), money("100"));
+        assert_eq!(partial.remaining_to_max(), money("60"));
+        assert!(!partial.is_full());
+        let full = leaf("Rent", "100", money("100"));
+        assert_eq!(full.remaining_to_max(), Money::ZERO);
+        assert!(full.is_full());
+        let root = sample();
+        assert_eq!(root.children()[1].account().max(), Money::UNLIMITED);
+        assert!(!root.is_full());
+    }
+
+    // root > Rent (fixed 50, max 50), Netflix (fixed -15), Fun (flex 1)
+    fn with_subscription(netflix: &str) -> Account {
+        let mut root = Account::new_root();
+        root.add_child(leaf("Rent", "0", money("50")), Fixed(money("50"))).unwrap();
+        root.add_child(leaf("Netflix", netflix, Money::UNLIMITED), Fixed(money("-15"))).unwrap();
+        root.add_child(leaf("Fun", "0", Money::UNLIMITED), Flex(1.0)).unwrap();
+        root
+    }
+
+    #[test]
+    fn negative_fixed_inflows_are_charged_once_a_month() {
+        let mut root = with_subscription("100");
+        root.new_month(money("80"));
+        assert_eq!(root.balance_of("Rent"), Some(money("50")));
+        assert_eq!(root.balance_of("Netflix"), Some(money("85")));
+        assert_eq!(root.balance_of("Fun"), Some(money("30")));
+        root.new_month(money("0"));
+        assert_eq!(root.balance_of("Netflix"), Some(money("70")));
+    }
+
+    #[test]
+    fn debits_are_not_charged_by_other_deposits() {
+        let mut root = with_subscription("100");
+        root.add_child(leaf("Old", "0", Money::UNLIMITED), Flex(1.0)).unwrap();
+        deposit(&mut root, None, "0");
+        deposit(&mut root, None, "20");
+        root.apply(Remove { name: "Old".to_owned() }).unwrap();
+        assert_eq!(root.balance_of("Netflix"), Some(money("100")));
+    }
+
+    #[test]
+    fn debits_stop_at_zero_unless_negatives_are_allowed() {
+        let mut root = with_subscription("10");
+        root.new_month(Money::ZERO);
+        assert_eq!(root.balance_of("Netflix"), Some(Money::ZERO));
+        if let Leaf { allow_negative, .. } = &mut root.find_child("Netflix").unwrap().data {
+            *allow_negative = true;
         }
+        root.new_month(Money::ZERO);
+        assert_eq!(root.balance_of("Netflix"), Some(money("-15")));
     }
 
-    fn make_fixed_deposit(&mut self, available: f64) -> f64 {
-        match self.inflow {
-            Fixed(take) => {
-                let take = take.min(self.until_max()).min(available);
-                self.account.deposit(take);
-                available - take
+    #[test]
+    fn debits_too_large_to_withdraw_are_refused() {
+        let mut root = with_subscription("0");
+        let huge = Fixed(Money::from_cents(-i64::MAX));
+        let set = SetInflow { account: "Netflix".to_owned(), inflow: huge.clone() };
+        assert_eq!(root.apply(set), Err(BudgetError::InvalidAmount(Money::from_cents(-i64::MAX))));
+        // Even if one gets in some other way, the month still starts
+        if let Leaf { allow_negative, .. } = &mut root.find_child("Netflix").unwrap().data {
+            *allow_negative = true;
+        }
+        root.entry_at(&["root".to_owned(), "Netflix".to_owned()]).inflow = huge;
+        root.new_month(money("10"));
+        assert_eq!(root.balance_of("Netflix"), Some(Money::ZERO));
+    }
+
+    #[test]
+    fn higher_priority_children_fill_first() {
+        let mut root = Account::new_root();
+        root.add_entry(BranchEntry { account: leaf("Low", "0", money("100")), inflow: Flex(3.0), priority: 0 }).unwrap();
+        root.add_entry(BranchEntry { account: leaf("High", "0", money("100")), inflow: Flex(1.0), priority: 1 }).unwrap();
+        deposit(&mut root, None, "60");
+        assert_eq!(root.balance_of("High"), Some(money("60")));
+        assert_eq!(root.balance_of("Low"), Some(Money::ZERO));
+        deposit(&mut root, None, "90");
+        assert_eq!(root.balance_of("High"), Some(money("100")));
+        assert_eq!(root.balance_of("Low"), Some(money("50")));
+    }
+
+    #[test]
+    fn children_with_the_same_priority_split_by_weight() {
+        let mut root = Account::new_root();
+        root.add_entry(BranchEntry { account: leaf("A", "0", Money::UNLIMITED), inflow: Flex(3.0), priority: 2 }).unwrap();
+        root.add_entry(BranchEntry { account: leaf("B", "0", Money::UNLIMITED), inflow: Flex(1.0), priority: 2 }).unwrap();
+        deposit(&mut root, None, "40");
+        assert_eq!(root.balance_of("A"), Some(money("30")));
+        assert_eq!(root.balance_of("B"), Some(money("10")));
+    }
+
+    #[test]
+    fn planning_a_deposit_matches_making_it() {
+        let root = sample();
+        let plan = root.plan_deposit(money("250"));
+        assert_eq!(root, sample());
+        let mut funded = sample();
+        assert_eq!(funded.deposit_detailed(money("250")), plan);
+        assert_eq!(plan.iter().map(|(_, amount)| *amount).sum::<Money>(), money("250"));
+    }
+
+    #[test]
+    fn detailed_deposits_report_every_leaf_that_was_funded() {
+        let mut root = sample();
+        let funded = root.deposit_detailed(money("400.01"));
+        assert_eq!(funded, vec![
+            ("Rent".to_owned(), money("100")),
+            ("Food".to_owned(), money("150.01")),
+            ("Fun".to_owned(), money("150")),
+        ]);
+        assert_eq!(funded.iter().map(|(_, amount)| *amount).sum::<Money>(), money("400.01"));
+    }
+
+    #[test]
+    fn transferring_to_a_new_account_creates_it() {
+        let mut root = sample();
+        deposit(&mut root, None, "100");
+        let transfer = TransferToNew { from: "Rent".to_owned(), to: "Gifts".to_owned(), parent: "Living".to_owned(), amount: money("40"), date: date(2020, 2, 1) };
+        root.apply(transfer).unwrap();
+        assert_eq!(root.path_of("Gifts"), Some("root/Living/Gifts".to_owned()));
+        assert_eq!(root.balance_of("Gifts"), Some(money("40")));
+        assert_eq!(root.balance_of("Rent"), Some(money("60")));
+        // It stays out of deposits until its inflow is changed
+        deposit(&mut root, None, "1000");
+        assert_eq!(root.balance_of("Gifts"), Some(money("40")));
+    }
+
+    #[test]
+    fn transferring_to_an_existing_account_is_a_plain_transfer() {
+        let mut root = sample();
+        deposit(&mut root, None, "100");
+        let transfer = TransferToNew { from: "Rent".to_owned(), to: "Food".to_owned(), parent: "root".to_owned(), amount: money("40"), date: date(2020, 2, 1) };
+        root.apply(transfer).unwrap();
+        assert_eq!(root.balance_of("Food"), Some(money("40")));
+        assert_eq!(root.path_of("Food"), Some("root/Living/Food".to_owned()));
+    }
+
+    #[test]
+    fn transferring_to_a_new_account_needs_its_parent() {
+        let mut root = sample();
+        deposit(&mut root, None, "100");
+        let transfer = TransferToNew { from: "Rent".to_owned(), to: "Gifts".to_owned(), parent: "Nowhere".to_owned(), amount: money("40"), date: date(2020, 2, 1) };
+        assert_eq!(root.apply(transfer), Err(BudgetError::AccountNotFound("Nowhere".to_owned())));
+        assert_eq!(root.balance_of("Rent"), Some(money("100")));
+    }
+
+    #[test]
+    fn unusable_amounts_and_weights_are_refused() {
+        assert!("NaN".parse::<Money>().is_err());
+        assert!("inf".parse::<Money>().is_err());
+        let mut root = sample();
+        let deposit = Deposit { account: None, amount: Money::UNLIMITED, date: date(2020, 1, 1) };
+        assert_eq!(root.apply(deposit), Err(BudgetError::InvalidAmount(Money::UNLIMITED)));
+        let withdraw = Withdraw { account: "Fun".to_owned(), amount: money("-5"), date: date(2020, 1, 1) };
+        assert_eq!(root.apply(withdraw), Err(BudgetError::InvalidAmount(money("-5"))));
+        let set = SetInflow { account: "Fun".to_owned(), inflow: Flex(f64::NAN) };
+        assert!(matches!(root.apply(set), Err(BudgetError::InvalidWeight(_))));
+        let set = SetInflow { account: "Fun".to_owned(), inflow: Flex(f64::INFINITY) };
+        assert_eq!(root.apply(set), Err(BudgetError::InvalidWeight(f64::INFINITY)));
+        assert_eq!(root, sample());
+    }
+
+    #[test]
+    fn leftovers_past_every_flex_max_are_still_handed_out() {
+        let mut root = Account::new_root();
+        for name in &["A", "B", "C"] {
+            root.add_child(leaf(name, "0", money("10")), Flex(1.0)).unwrap();
+        }
+        deposit(&mut root, None, "30.02");
+        assert_eq!(root.balance_of("A"), Some(money("10.01")));
+        assert_eq!(root.balance_of("B"), Some(money("10.01")));
+        assert_eq!(root.balance_of("C"), Some(money("10")));
+    }
+
+    #[test]
+    fn children_lists_only_the_direct_children() {
+        let mut root = sample();
+        root.add_child(leaf("Savings", "0", Money::UNLIMITED), Percent(10.0)).unwrap();
+        let children: Vec<(&str, &Inflow)> = root.children().iter().map(|child| (child.account().name(), child.inflow())).collect();
+        assert_eq!(children, vec![("Rent", &Fixed(money("100"))), ("Living", &Flex(1.0)), ("Savings", &Percent(10.0))]);
+        assert!(root.find_child("Rent").unwrap().children().is_empty());
+    }
+
+    #[test]
+    fn report_shows_maxes_progress_and_inflows() {
+        let mut root = sample();
+        deposit(&mut root, None, "150");
+        assert_eq!(root.report(), "root: 150.00 of unlimited
+  Rent: 100.00 of 100.00 (100%) [Fixed(100.00)]
+  Living: 50.00 of unlimited [Flex(1.0)]
+    Food: 25.00 of 200.00 (12%) [Flex(1.0)]
+    Fun: 25.00 of unlimited [Flex(1.0)]
+");
+    }
+
+    #[test]
+    fn renaming_the_root_keeps_lookups_working() {
+        let mut root = sample();
+        root.rename_root("Household".to_owned()).unwrap();
+        assert_eq!(root.find_child("Household").map(|account| account.name().to_owned()), Some("Household".to_owned()));
+        assert_eq!(root.path_of("Food"), Some("Household/Living/Food".to_owned()));
+        deposit(&mut root, Some("Household/Living/Food"), "5");
+        assert_eq!(root.balance_of("Food"), Some(money("5")));
+        assert_eq!(root.rename_root("Food".to_owned()), Err(BudgetError::DuplicateName("Food".to_owned())));
+    }
+
+    #[test]
+    fn the_root_cant_take_an_archived_account_name() {
+        let mut root = sample();
+        root.apply(Archive { account: "Fun".to_owned() }).unwrap();
+        assert_eq!(root.rename_root("Fun".to_owned()), Err(BudgetError::DuplicateName("Fun".to_owned())));
+        assert_eq!(root.name(), "root");
+    }
+
+    #[test]
+    fn setting_an_inflow_changes_the_next_deposit() {
+        let mut root = sample();
+        let set = SetInflow { account: "Rent".to_owned(), inflow: Flex(2.0) };
+        root.apply(set).unwrap();
+        assert_eq!(root.inflow_of("Rent"), Some(&Flex(2.0)));
+        deposit(&mut root, None, "90");
+        assert_eq!(root.balance_of("Rent"), Some(money("60")));
+        assert_eq!(root.balance_of("Food"), Some(money("15")));
+        let set = SetInflow { account: "root".to_owned(), inflow: Flex(1.0) };
+        assert!(root.apply(set).is_err());
+    }
+
+    #[test]
+    fn raising_a_max_lets_the_leaf_take_more() {
+        let mut root = Account::new_root();
+        root.add_child(leaf("Rent", "0", money("100")), Fixed(money("500"))).unwrap();
+        root.add_child(leaf("Fun", "0", Money::UNLIMITED), Flex(1.0)).unwrap();
+        deposit(&mut root, None, "400");
+        assert_eq!(root.balance_of("Rent"), Some(money("100")));
+        root.apply(SetMax { account: "Rent".to_owned(), max: money("250") }).unwrap();
+        assert_eq!(root.find_child("Rent").unwrap().remaining_to_max(), money("150"));
+        deposit(&mut root, None, "400");
+        assert_eq!(root.balance_of("Rent"), Some(money("250")));
+        assert_eq!(root.balance_of("Fun"), Some(money("550")));
+    }
+
+    #[test]
+    fn branches_have_no_max_to_set() {
+        let mut root = sample();
+        let set = SetMax { account: "Living".to_owned(), max: money("10") };
+        assert_eq!(root.apply(set), Err(BudgetError::BranchMax("Living".to_owned())));
+    }
+
+    #[test]
+    fn inflows_parse_from_their_compact_form() {
+        assert_eq!("fixed:100".parse::<Inflow>(), Ok(Fixed(money("100"))));
+        assert_eq!("FLEX:3".parse::<Inflow>(), Ok(Flex(3.0)));
+        assert_eq!("Percent:12.5".parse::<Inflow>(), Ok(Percent(12.5)));
+        assert_eq!("fill".parse::<Inflow>(), Ok(FillToMax));
+        for inflow in &[Fixed(money("100")), Flex(2.0), Percent(10.0), FillToMax] {
+            assert_eq!(inflow.to_string().parse::<Inflow>().as_ref(), Ok(inflow));
+        }
+    }
+
+    #[test]
+    fn malformed_inflows_are_refused() {
+        assert_eq!("flex".parse::<Inflow>(), Err("Expected an inflow like fixed:100, flex:3, percent:10, or fill, found flex".to_owned()));
+        assert!("flex:lots".parse::<Inflow>().is_err());
+        assert!("weekly:10".parse::<Inflow>().is_err());
+        assert!("fixed:1.234".parse::<Inflow>().is_err());
+    }
+
+    #[test]
+    fn emptying_a_leaf_leaves_exactly_nothing() {
+        let mut root = sample();
+        deposit(&mut root, None, "100.03");
+        root.apply(Empty { account: "Rent".to_owned(), date: date(2020, 1, 2) }).unwrap();
+        assert_eq!(root.balance_of("Rent"), Some(Money::ZERO));
+        assert_eq!(root.balance(), money("0.03"));
+    }
+
+    #[test]
+    fn emptying_a_branch_empties_its_leaves() {
+        let mut root = sample();
+        deposit(&mut root, None, "300");
+        root.apply(Empty { account: "Living".to_owned(), date: date(2020, 1, 2) }).unwrap();
+        assert_eq!(root.balance_of("Food"), Some(Money::ZERO));
+        assert_eq!(root.balance_of("Fun"), Some(Money::ZERO));
+        assert_eq!(root.balance(), money("100"));
+    }
+
+    #[test]
+    fn invariant_checks_report_corrupted_balances() {
+        let mut root = sample();
+        deposit(&mut root, None, "250");
+        assert_eq!(root.check_invariants(), Ok(()));
+        if let Leaf { balance, .. } = &mut root.find_child("Food").unwrap().data {
+            *balance = money("-5");
+        }
+        if let Leaf { balance, .. } = &mut root.find_child("Fun").unwrap().data {
+            *balance = Money::UNLIMITED;
+        }
+        assert_eq!(root.check_invariants(), Err("Food has a negative balance of -5.00 but doesn't allow one\n\
+            Fun has a balance of unlimited, which has overflowed".to_owned()));
+    }
+
+    #[test]
+    fn invariant_checks_report_duplicate_names() {
+        let mut root = sample();
+        if let Branch { children, .. } = &mut root.data {
+            let copy = children[0].clone();
+            children.push(copy);
+        }
+        assert_eq!(root.check_invariants(), Err("root has more than one child named Rent".to_owned()));
+    }
+
+    #[test]
+    fn depth_and_count_cover_the_whole_tree() {
+        let root = sample();
+        assert_eq!(root.depth(), 2);
+        assert_eq!(root.account_count(), 5);
+        let leaf = leaf("Food", "0", Money::UNLIMITED);
+        assert_eq!(leaf.depth(), 0);
+        assert_eq!(leaf.account_count(), 1);
+    }
+
+    #[test]
+    fn bare_names_matching_two_accounts_are_ambiguous() {
+        let mut root = two_foods();
+        deposit(&mut root, None, "100");
+        let withdraw = |account: &str| Withdraw { account: account.to_owned(), amount: money("10"), date: date(2020, 1, 2) };
+        assert_eq!(root.apply(withdraw("Food")), Err(BudgetError::AmbiguousName("Food".to_owned())));
+        root.apply(withdraw("root/Work/Food")).unwrap();
+        root.apply(withdraw("root/Home/Food")).unwrap();
+        assert_eq!(root.balance(), money("80"));
+        deposit(&mut root, Some("Home"), "5");
+        assert_eq!(root.balance(), money("85"));
+    }
+
+    #[test]
+    fn resetting_balances_keeps_the_structure() {
+        let mut root = sample();
+        deposit(&mut root, None, "500");
+        let before = root.clone();
+        root.reset_balances();
+        assert_eq!(root.balance(), Money::ZERO);
+        assert!(root.same_structure(&before));
+        assert_eq!(root.account_count(), before.account_count());
+        assert_eq!(root.find_child("Food").unwrap().max(), money("200"));
+    }
+
+    #[test]
+    fn the_builder_matches_applying_new_actions() {
+        let new = |name: &str, inflow, parent: &str, data| New {
+            name: name.to_owned(), inflow, priority: 0, parent: parent.to_owned(), data, note: None, created: None
+        };
+        let empty_leaf = |max| Leaf { balance: Money::ZERO, max, allow_negative: false, rollover: Rollover::Keep, goal: None, currency: None };
+        let applied = Account::replay(vec![
+            new("Rent", Fixed(money("100")), "root", empty_leaf(money("100"))),
+            new("Living", Flex(1.0), "root", Branch { children: Vec::new(), overflow: None, unit: Money::CENT }),
+            new("Food", Flex(1.0), "Living", empty_leaf(money("200"))),
+            new("Fun", Flex(1.0), "Living", empty_leaf(Money::UNLIMITED)),
+        ]).unwrap();
+        assert_eq!(sample(), applied);
+    }
+
+    #[test]
+    fn the_builder_reports_the_first_bad_child() {
+        let built = AccountBuilder::new("root")
+            .leaf("Food", Flex(1.0), Money::UNLIMITED)
+            .branch("Living", Flex(1.0), |living| living.leaf("Fun", Flex(-1.0), Money::UNLIMITED))
+            .leaf("Food", Flex(1.0), Money::UNLIMITED)
+            .build();
+        assert_eq!(built, Err(BudgetError::InvalidWeight(-1.0)));
+    }
+
+    #[test]
+    fn branches_of_zero_weight_children_are_warned_about() {
+        let root = AccountBuilder::new("root")
+            .leaf("Rent", Fixed(money("100")), money("100"))
+            .branch("Spare", Flex(1.0), |spare| spare
+                .leaf("A", Flex(0.0), Money::UNLIMITED)
+                .leaf("B", Flex(0.0), Money::UNLIMITED))
+            .build()
+            .unwrap();
+        assert_eq!(root.total_flex_weight(), 1.0);
+        assert_eq!(root.funding_warnings(), vec![FundingWarning::UnfundedBranch("Spare".to_owned())]);
+        assert_eq!(sample().funding_warnings(), Vec::new());
+    }
+
+    #[test]
+    fn leftover_cents_go_to_the_earliest_children() {
+        let mut root = Account::new_root();
+        for name in &["A", "B", "C"] {
+            root.add_child(leaf(name, "0", Money::ZERO), Flex(1.0)).unwrap();
+        }
+        deposit(&mut root, None, "100");
+        assert_eq!(root.balance_of("A"), Some(money("33.34")));
+        assert_eq!(root.balance_of("B"), Some(money("33.33")));
+        assert_eq!(root.balance_of("C"), Some(money("33.33")));
+        deposit(&mut root, None, "0.02");
+        assert_eq!(root.balance_of("A"), Some(money("33.35")));
+        assert_eq!(root.balance_of("B"), Some(money("33.34")));
+        assert_eq!(root.balance(), money("100.02"));
+    }
+
+    #[test]
+    fn actions_round_trip_through_json_and_replay() {
+        let empty_leaf = |max| Leaf { balance: Money::ZERO, max, allow_negative: false, rollover: Rollover::Keep, goal: None, currency: None };
+        let actions = vec![
+            New { name: "Rent".to_owned(), inflow: Fixed(money("100")), priority: 0, parent: "root".to_owned(), data: empty_leaf(money("100")), note: None, created: None },
+            New { name: "Fun".to_owned(), inflow: Flex(1.0), priority: 0, parent: "root".to_owned(), data: empty_leaf(Money::UNLIMITED), note: None, created: None },
+            Deposit { account: None, amount: money("150"), date: date(2020, 1, 1) },
+            Withdraw { account: "Fun".to_owned(), amount: money("20"), date: date(2020, 1, 2) },
+        ];
+        let json = serde_json::to_string(&actions).unwrap();
+        let loaded: Vec<Action> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, actions);
+        let mut built = Account::new_root();
+        built.add_child(leaf("Rent", "100", money("100")), Fixed(money("100"))).unwrap();
+        built.add_child(leaf("Fun", "30", Money::UNLIMITED), Flex(1.0)).unwrap();
+        let mut replayed = Account::replay(loaded).unwrap();
+        assert_eq!(replayed.balance_of("Rent"), built.balance_of("Rent"));
+        assert_eq!(replayed.balance_of("Fun"), built.balance_of("Fun"));
+        assert!(replayed.same_structure(&built));
+        assert_eq!(replayed.find_child("Fun").unwrap().last_activity, Some(date(2020, 1, 2)));
+    }
+
+    #[test]
+    fn deposits_into_a_leaf_go_past_its_max() {
+        let mut root = sample();
+        deposit(&mut root, Some("Rent"), "150");
+        assert_eq!(root.balance_of("Rent"), Some(money("150")));
+    }
+
+    #[test]
+    fn forced_deposits_send_the_excess_to_one_descendant() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("Rent", Fixed(money("100")), money("100"))
+            .leaf("Food", Flex(1.0), money("200"))
+            .leaf("Gym", Flex(1.0), money("50"))
+            .build()
+            .unwrap();
+        root.deposit_forced(money("500"), "Food").unwrap();
+        assert_eq!(root.balance_of("Rent"), Some(money("100")));
+        assert_eq!(root.balance_of("Food"), Some(money("350")));
+        assert_eq!(root.balance_of("Gym"), Some(money("50")));
+        assert_eq!(root.deposit_forced(money("5"), "Nowhere"), Err(BudgetError::AccountNotFound("Nowhere".to_owned())));
+    }
+
+    #[test]
+    fn the_tree_displays_in_a_given_format() {
+        let mut root = AccountBuilder::new("root").leaf("Rent", Fixed(money("2000")), Money::UNLIMITED).build().unwrap();
+        deposit(&mut root, None, "1234.5");
+        let usd = CurrencyFormat { symbol: "$".to_owned(), decimal_sep: '.', thousands_sep: Some(',') };
+        assert_eq!(root.display_with(&usd), "Flex(1.0):\troot: $1,234.50\nFixed(2000.00):\t  Rent: $1,234.50\n");
+    }
+
+    // root > Checking (USD), Savings (EUR), each with 100 in its own currency
+    fn two_currencies() -> Account {
+        let mut root = Account::new_root();
+        let in_currency = |name: &str, code: &str| {
+            let mut account = leaf(name, "100", Money::UNLIMITED);
+            if let Leaf { currency, .. } = &mut account.data {
+                *currency = Some(code.to_owned());
             }
-            Flex(_) => available
+            account
+        };
+        root.add_child(in_currency("Checking", "USD"), Flex(1.0)).unwrap();
+        root.add_child(in_currency("Savings", "EUR"), Flex(1.0)).unwrap();
+        root
+    }
+
+    #[test]
+    fn transfers_between_currencies_convert_at_the_rate() {
+        let mut root = two_currencies();
+        let transfer = TransferFx { from: "Savings".to_owned(), to: "Checking".to_owned(), amount: money("50"), rate: 1.1, date: date(2020, 1, 1) };
+        root.apply(transfer).unwrap();
+        assert_eq!(root.balance_of("Savings"), Some(money("50")));
+        assert_eq!(root.balance_of("Checking"), Some(money("155")));
+        assert_eq!(root.checked_balance(), Err(BudgetError::MixedCurrencies("root".to_owned())));
+    }
+
+    #[test]
+    fn plain_transfers_between_currencies_are_refused() {
+        let mut root = two_currencies();
+        let transfer = Transfer { from: "Savings".to_owned(), to: Some("Checking".to_owned()), amount: money("50"), date: date(2020, 1, 1) };
+        assert_eq!(root.apply(transfer), Err(BudgetError::MixedCurrencies("Checking".to_owned())));
+        assert_eq!(root.balance_of("Savings"), Some(money("100")));
+        assert_eq!(root.balance_of("Checking"), Some(money("100")));
+    }
+
+    #[test]
+    fn filter_finds_every_matching_account() {
+        let mut root = sample();
+        deposit(&mut root, None, "250");
+        let names: Vec<&str> = root.filter(|account| account.is_leaf() && account.balance() >= money("75"))
+            .into_iter()
+            .map(Account::name)
+            .collect();
+        assert_eq!(names, vec!["Rent", "Food", "Fun"]);
+        let names: Vec<&str> = root.filter(|account| account.balance() > money("100")).into_iter().map(Account::name).collect();
+        assert_eq!(names, vec!["root", "Living"]);
+    }
+
+    #[test]
+    fn the_default_account_is_an_empty_root() {
+        let root = Account::default();
+        assert_eq!(root.name(), "root");
+        assert_eq!(root.balance(), Money::ZERO);
+        assert_eq!(root, Account::new_root());
+    }
+
+    #[test]
+    fn inflows_can_be_read_back() {
+        let root = sample();
+        assert_eq!(root.inflow_of("Rent"), Some(&Fixed(money("100"))));
+        assert_eq!(root.inflow_of("Food"), Some(&Flex(1.0)));
+        assert_eq!(root.inflow_of("root"), None);
+        assert_eq!(root.inflow_of("Nowhere"), None);
+    }
+
+    #[test]
+    fn balance_changes_mark_accounts_active() {
+        let mut root = sample();
+        deposit(&mut root, None, "100");
+        root.apply(Withdraw { account: "Rent".to_owned(), amount: money("10"), date: date(2020, 3, 4) }).unwrap();
+        assert_eq!(root.find_child("Rent").unwrap().last_activity(), Some(date(2020, 3, 4)));
+        // Nothing was left for Living's children after Rent took it all
+        assert_eq!(root.find_child("Food").unwrap().last_activity(), None);
+        deposit(&mut root, Some("Food"), "5");
+        assert_eq!(root.find_child("Living").unwrap().last_activity(), Some(date(2020, 1, 1)));
+        assert_eq!(root.last_activity(), Some(date(2020, 3, 4)));
+    }
+
+    #[test]
+    fn changes_since_lists_the_leaves_that_changed() {
+        let mut root = sample();
+        deposit(&mut root, None, "100");
+        let before = root.clone();
+        deposit(&mut root, Some("Food"), "20");
+        root.apply(Withdraw { account: "Rent".to_owned(), amount: money("30"), date: date(2020, 1, 2) }).unwrap();
+        assert_eq!(root.changes_since(&before), vec![
+            AccountDiff::Changed { path: "root/Rent".to_owned(), before: money("100"), after: money("70") },
+            AccountDiff::Changed { path: "root/Living/Food".to_owned(), before: Money::ZERO, after: money("20") },
+        ]);
+    }
+
+    #[test]
+    fn changes_since_lists_added_and_removed_leaves() {
+        let mut root = sample();
+        deposit(&mut root, None, "100");
+        let before = root.clone();
+        root.add_child(leaf("Gifts", "0", Money::UNLIMITED), Flex(1.0)).unwrap();
+        root.apply(Remove { name: "Rent".to_owned() }).unwrap();
+        let changes = root.changes_since(&before);
+        assert_eq!(changes[changes.len() - 1], AccountDiff::Removed { path: "root/Rent".to_owned(), balance: money("100") });
+        assert!(changes.iter().any(|change| matches!(change, AccountDiff::Added { path, .. } if path == "root/Gifts")));
+    }
+
+    #[test]
+    fn flex_weights_split_to_the_exact_cent() {
+        let mut root = Account::new_root();
+        for name in &["A", "B", "C"] {
+            root.add_child(leaf(name, "0", Money::UNLIMITED), Flex(1.0)).unwrap();
         }
+        deposit(&mut root, None, "100");
+        assert_eq!(root.balance_of("A"), Some(money("33.34")));
+        assert_eq!(root.balance_of("B"), Some(money("33.33")));
+        assert_eq!(root.balance_of("C"), Some(money("33.33")));
     }
 
-    fn make_flex_deposit(&mut self, available: f64, per_flex: f64) -> f64 {
-        match self.inflow {
-            Flex(flex) => {
-                let take = (per_flex * flex).min(available).min(self.until_max());
-                self.account.deposit(take);
-                available - take
-            },
-            _ => available
+    #[test]
+    fn fractional_flex_weights_split_to_the_exact_cent() {
+        let mut root = Account::new_root();
+        root.add_child(leaf("A", "0", Money::UNLIMITED), Flex(0.333)).unwrap();
+        root.add_child(leaf("B", "0", Money::UNLIMITED), Flex(0.667)).unwrap();
+        deposit(&mut root, None, "10.01");
+        assert_eq!(root.balance_of("A"), Some(money("3.33")));
+        assert_eq!(root.balance_of("B"), Some(money("6.68")));
+    }
+
+    #[test]
+    fn path_of_names_every_ancestor() {
+        let root = AccountBuilder::new("root")
+            .branch("Home", Flex(1.0), |home| home
+                .branch("Kitchen", Flex(1.0), |kitchen| kitchen.leaf("Food", Flex(1.0), Money::UNLIMITED)))
+            .build()
+            .unwrap();
+        assert_eq!(root.path_of("Food"), Some("root/Home/Kitchen/Food".to_owned()));
+        assert_eq!(root.path_of("root"), Some("root".to_owned()));
+        assert_eq!(root.path_of("Nowhere"), None);
+    }
+
+    #[test]
+    fn multi_deposits_fund_each_account() {
+        let mut root = sample();
+        let entries = vec![("Food".to_owned(), money("20")), ("Rent".to_owned(), money("150"))];
+        root.apply(MultiDeposit { entries, date: date(2020, 1, 1) }).unwrap();
+        assert_eq!(root.balance_of("Food"), Some(money("20")));
+        assert_eq!(root.balance_of("Rent"), Some(money("150")));
+    }
+
+    #[test]
+    fn multi_deposits_with_an_unknown_account_apply_nothing() {
+        let mut root = sample();
+        let entries = vec![("Food".to_owned(), money("20")), ("Nowhere".to_owned(), money("5"))];
+        let missing = BudgetError::TargetNotFound { name: "Nowhere".to_owned(), action: "deposit to" };
+        assert_eq!(root.apply(MultiDeposit { entries, date: date(2020, 1, 1) }), Err(missing));
+        assert_eq!(root, sample());
+    }
+
+    #[test]
+    fn sorting_children_reorders_without_changing_balances() {
+        let mut root = Account::new_root();
+        root.add_child(leaf("B", "5", Money::UNLIMITED), Flex(1.0)).unwrap();
+        root.add_child(leaf("C", "20", Money::UNLIMITED), Flex(1.0)).unwrap();
+        root.add_child(leaf("A", "10", Money::UNLIMITED), Flex(1.0)).unwrap();
+        let names = |root: &Account| root.children().iter().map(|child| child.account().name().to_owned()).collect::<Vec<_>>();
+        root.sort_children_by_name();
+        assert_eq!(names(&root), vec!["A", "B", "C"]);
+        root.sort_children_by_balance();
+        assert_eq!(names(&root), vec!["C", "A", "B"]);
+        assert_eq!(root.balance_of("A"), Some(money("10")));
+        assert_eq!(root.balance(), money("35"));
+    }
+
+    #[test]
+    fn capped_deposits_hand_back_what_doesnt_fit() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("Rent", Fixed(money("100")), money("100"))
+            .branch("Living", Flex(1.0), |living| living
+                .leaf("Food", Flex(1.0), money("200"))
+                .leaf("Gym", Flex(3.0), money("50")))
+            .build()
+            .unwrap();
+        assert_eq!(root.deposit_capped(money("400")), money("50"));
+        assert_eq!(root.balance_of("Rent"), Some(money("100")));
+        assert_eq!(root.balance_of("Food"), Some(money("200")));
+        assert_eq!(root.balance_of("Gym"), Some(money("50")));
+        assert_eq!(root.deposit_capped(money("10")), money("10"));
+    }
+
+    #[test]
+    fn fill_ratios_run_from_empty_to_full() {
+        let mut root = Account::new_root();
+        root.add_child(leaf("Half", "50", money("100")), Flex(1.0)).unwrap();
+        root.add_child(leaf("Over", "150", money("100")), Flex(1.0)).unwrap();
+        root.add_child(leaf("Empty", "0", Money::ZERO), Flex(1.0)).unwrap();
+        root.add_child(leaf("Funded", "1", Money::ZERO), Flex(1.0)).unwrap();
+        let ratio = |root: &mut Account, name: &str| root.find_child(name).unwrap().fill_ratio();
+        assert_eq!(ratio(&mut root, "Half"), 0.5);
+        assert_eq!(ratio(&mut root, "Over"), 1.0);
+        assert_eq!(ratio(&mut root, "Empty"), 0.0);
+        assert_eq!(ratio(&mut root, "Funded"), 1.0);
+        // The whole tree holds 201 against maxes adding up to 200
+        assert_eq!(root.fill_ratio(), 1.0);
+    }
+
+    #[test]
+    fn fill_to_max_tops_an_account_up() {
+        let mut root = Account::new_root();
+        root.add_child(leaf("Car", "30", money("100")), FillToMax).unwrap();
+        root.add_child(leaf("Fun", "0", Money::UNLIMITED), Flex(1.0)).unwrap();
+        deposit(&mut root, None, "100");
+        assert_eq!(root.balance_of("Car"), Some(money("100")));
+        assert_eq!(root.balance_of("Fun"), Some(money("30")));
+        deposit(&mut root, None, "50");
+        assert_eq!(root.balance_of("Car"), Some(money("100")));
+        assert_eq!(root.balance_of("Fun"), Some(money("80")));
+    }
+
+    #[test]
+    fn merging_folds_one_leaf_into_another() {
+        let mut root = sample();
+        deposit(&mut root, None, "300");
+        root.apply(Merge { source: "Fun".to_owned(), dest: "Food".to_owned() }).unwrap();
+        assert_eq!(root.balance_of("Food"), Some(money("200")));
+        assert_eq!(root.balance_of("Fun"), None);
+        assert_eq!(root.balance(), money("300"));
+    }
+
+    #[test]
+    fn only_leaves_can_be_merged() {
+        let mut root = sample();
+        assert_eq!(root.apply(Merge { source: "Living".to_owned(), dest: "Rent".to_owned() }), Err(BudgetError::NotLeaf("Living".to_owned())));
+        assert_eq!(root.apply(Merge { source: "Rent".to_owned(), dest: "Living".to_owned() }), Err(BudgetError::NotLeaf("Living".to_owned())));
+        assert_eq!(root.apply(Merge { source: "Rent".to_owned(), dest: "Nowhere".to_owned() }), Err(BudgetError::AccountNotFound("Nowhere".to_owned())));
+        assert_eq!(root, sample());
+    }
+
+    #[test]
+    fn splitting_a_leaf_spreads_its_balance_over_new_children() {
+        let mut root = sample();
+        deposit(&mut root, Some("Fun"), "90");
+        let children = vec![
+            ("Movies".to_owned(), Fixed(money("20")), money("20")),
+            ("Games".to_owned(), Flex(1.0), Money::UNLIMITED),
+        ];
+        root.apply(Split { account: "Fun".to_owned(), children }).unwrap();
+        assert_eq!(root.path_of("Movies"), Some("root/Living/Fun/Movies".to_owned()));
+        assert_eq!(root.balance_of("Movies"), Some(money("20")));
+        assert_eq!(root.balance_of("Games"), Some(money("70")));
+        assert_eq!(root.balance_of("Fun"), Some(money("90")));
+    }
+
+    #[test]
+    fn only_leaves_can_be_split() {
+        let mut root = sample();
+        let split = Split { account: "Living".to_owned(), children: vec![("Gym".to_owned(), Flex(1.0), Money::UNLIMITED)] };
+        assert_eq!(root.apply(split), Err(BudgetError::NotLeaf("Living".to_owned())));
+    }
+
+    #[test]
+    fn branch_maxes_add_up_every_leaf_below() {
+        let root = AccountBuilder::new("root")
+            .leaf("Rent", Fixed(money("100")), money("100"))
+            .branch("Living", Flex(1.0), |living| living
+                .leaf("Food", Flex(1.0), money("200"))
+                .branch("Fun", Flex(1.0), |fun| fun
+                    .leaf("Movies", Flex(1.0), money("30"))
+                    .leaf("Games", Flex(1.0), money("20"))))
+            .build()
+            .unwrap();
+        assert_eq!(root.max(), money("350"));
+        assert_eq!(sample().max(), Money::UNLIMITED);
+    }
+
+    #[test]
+    fn trees_load_from_toml() {
+        let root = Account::from_toml(r#"
+            [[account]]
+            name = "Rent"
+            inflow = "fixed:100"
+            max = "100"
+
+            [[account]]
+            name = "Living"
+            inflow = "flex:1"
+            branch = true
+
+            [[account]]
+            name = "Food"
+            parent = "Living"
+            inflow = "flex:1"
+            max = "200"
+
+            [[account]]
+            name = "Fun"
+            parent = "Living"
+            inflow = "flex:1"
+        "#).unwrap();
+        assert_eq!(root, sample());
+    }
+
+    #[test]
+    fn toml_accounts_need_their_parents_first() {
+        let loaded = Account::from_toml(r#"
+            [[account]]
+            name = "Food"
+            parent = "Living"
+            inflow = "flex:1"
+        "#);
+        assert_eq!(loaded, Err("Could not find account Living".to_owned()));
+    }
+
+    #[test]
+    fn the_hook_sees_each_successful_action() {
+        let mut root = sample();
+        let mut seen = Vec::new();
+        let mut hook = |action: &Action, root: &Account| seen.push((action.date(), root.balance()));
+        root.apply_with_hook(Deposit { account: None, amount: money("50"), date: date(2020, 1, 1) }, &mut hook).unwrap();
+        root.apply_with_hook(Withdraw { account: "Rent".to_owned(), amount: money("10"), date: date(2020, 1, 2) }, &mut hook).unwrap();
+        assert!(root.apply_with_hook(Withdraw { account: "Rent".to_owned(), amount: money("100"), date: date(2020, 1, 3) }, &mut hook).is_err());
+        root.apply_with_hook(Rename { name: "Fun".to_owned(), new_name: "Games".to_owned() }, &mut hook).unwrap();
+        assert_eq!(seen, vec![(Some(date(2020, 1, 1)), money("50")), (Some(date(2020, 1, 2)), money("40")), (None, money("40"))]);
+    }
+
+    #[test]
+    fn deposits_into_a_branch_report_the_distribution() {
+        let mut root = sample();
+        let outcome = root.apply_reporting(Deposit { account: Some("Living".to_owned()), amount: money("50"), date: date(2020, 1, 1) });
+        assert_eq!(outcome, Ok(ActionOutcome::Distribution(vec![("Food".to_owned(), money("25")), ("Fun".to_owned(), money("25"))])));
+        let outcome = root.apply_reporting(Withdraw { account: "Food".to_owned(), amount: money("5"), date: date(2020, 1, 2) });
+        assert_eq!(outcome, Ok(ActionOutcome::Balance { account: "Food".to_owned(), balance: money("20") }));
+        assert_eq!(root.apply_reporting(Freeze { account: "Food".to_owned() }), Ok(ActionOutcome::Applied));
+    }
+
+    #[test]
+    fn frozen_accounts_cant_be_withdrawn_from() {
+        let mut root = sample();
+        deposit(&mut root, None, "100");
+        let withdraw = || Withdraw { account: "Rent".to_owned(), amount: money("10"), date: date(2020, 1, 2) };
+        root.apply(Freeze { account: "Rent".to_owned() }).unwrap();
+        assert_eq!(root.apply(withdraw()), Err(BudgetError::Frozen("Rent".to_owned())));
+        assert_eq!(root.apply(Remove { name: "Rent".to_owned() }), Err(BudgetError::Frozen("Rent".to_owned())));
+        let split = Split { account: "Rent".to_owned(), children: vec![("Deposit".to_owned(), Flex(1.0), Money::UNLIMITED)] };
+        assert_eq!(root.apply(split), Err(BudgetError::Frozen("Rent".to_owned())));
+        assert_eq!(root.balance_of("Rent"), Some(money("100")));
+        root.apply(Unfreeze { account: "Rent".to_owned() }).unwrap();
+        root.apply(withdraw()).unwrap();
+        assert_eq!(root.balance_of("Rent"), Some(money("90")));
+    }
+
+    #[test]
+    fn freezing_a_branch_freezes_its_children() {
+        let mut root = sample();
+        deposit(&mut root, Some("Living"), "100");
+        root.apply(Freeze { account: "Living".to_owned() }).unwrap();
+        assert!(root.find_child("Food").unwrap().is_frozen());
+        let withdraw = Withdraw { account: "Food".to_owned(), amount: money("10"), date: date(2020, 1, 2) };
+        assert_eq!(root.apply(withdraw), Err(BudgetError::Frozen("Food".to_owned())));
+        // Deposits still go in
+        deposit(&mut root, Some("Food"), "5");
+        assert_eq!(root.balance_of("Food"), Some(money("55")));
+    }
+
+    #[test]
+    fn alerts_list_overdrawn_and_full_accounts() {
+        let mut root = sample();
+        if let Leaf { allow_negative, .. } = &mut root.find_child("Fun").unwrap().data {
+            *allow_negative = true;
+        }
+        deposit(&mut root, Some("Rent"), "120");
+        root.apply(Withdraw { account: "Fun".to_owned(), amount: money("15"), date: date(2020, 1, 2) }).unwrap();
+        assert_eq!(root.alerts(), vec![
+            Alert { path: "root/Rent".to_owned(), reason: AlertReason::AtMax { balance: money("120"), max: money("100") } },
+            Alert { path: "root/Living/Fun".to_owned(), reason: AlertReason::Negative(money("-15")) },
+        ]);
+    }
+
+    #[test]
+    fn alerts_list_branches_holding_more_than_their_leaves_can() {
+        let mut root = AccountBuilder::new("root")
+            .branch("Living", Flex(1.0), |living| living.leaf("Food", Flex(1.0), money("200")))
+            .build()
+            .unwrap();
+        deposit(&mut root, None, "250");
+        assert_eq!(root.alerts().last(), Some(&Alert { path: "root/Living/Food".to_owned(), reason: AlertReason::AtMax { balance: money("250"), max: money("200") } }));
+        assert!(root.alerts().contains(&Alert { path: "root".to_owned(), reason: AlertReason::OverMax { balance: money("250"), max: money("200") } }));
+    }
+
+    #[test]
+    fn leftovers_go_where_there_is_room() {
+        let mut root = Account::new_root();
+        root.add_child(leaf("Nearly", "90", money("100")), Fixed(Money::ZERO)).unwrap();
+        root.add_child(leaf("Empty", "0", money("100")), Fixed(Money::ZERO)).unwrap();
+        deposit(&mut root, None, "55");
+        assert_eq!(root.balance_of("Nearly"), Some(money("95")));
+        assert_eq!(root.balance_of("Empty"), Some(money("50")));
+        // Past every max, the rest is split evenly
+        deposit(&mut root, None, "65");
+        assert_eq!(root.balance_of("Nearly"), Some(money("105")));
+        assert_eq!(root.balance_of("Empty"), Some(money("105")));
+    }
+
+    #[test]
+    fn archived_accounts_are_left_out_but_kept() {
+        let mut root = sample();
+        deposit(&mut root, None, "150");
+        root.apply(Archive { account: "Rent".to_owned() }).unwrap();
+        assert_eq!(root.balance(), money("50"));
+        assert!(root.iter().all(|account| account.name() != "Rent"));
+        let rent = root.iter_all().find(|account| account.name() == "Rent").unwrap();
+        assert!(rent.is_archived());
+        assert_eq!(rent.balance(), money("100"));
+        assert!(!root.to_string().contains("Rent"));
+        assert_eq!(root.path_of("Rent"), None);
+        assert_eq!(root.leaf_paths(), vec!["root/Living/Food", "root/Living/Fun"]);
+    }
+
+    #[test]
+    fn archived_accounts_take_no_deposits() {
+        let mut root = sample();
+        deposit(&mut root, Some("Fun"), "150");
+        root.apply(Archive { account: "Food".to_owned() }).unwrap();
+        let into_food = Deposit { account: Some("Food".to_owned()), amount: money("5"), date: date(2020, 1, 2) };
+        assert_eq!(root.apply(into_food), Err(BudgetError::Archived("Food".to_owned())));
+        assert_eq!(root.apply(Merge { source: "Fun".to_owned(), dest: "Food".to_owned() }), Err(BudgetError::Archived("Food".to_owned())));
+        // Deposits into Living all go to Fun now
+        deposit(&mut root, Some("Living"), "10");
+        assert_eq!(root.balance_of("Fun"), Some(money("160")));
+    }
+
+    #[test]
+    fn archived_accounts_raise_no_alerts() {
+        let mut root = sample();
+        deposit(&mut root, Some("Rent"), "150");
+        root.apply(Archive { account: "Rent".to_owned() }).unwrap();
+        assert_eq!(root.alerts(), Vec::new());
+    }
+
+    #[test]
+    fn required_income_covers_every_fixed_inflow() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("Rent", Fixed(money("1000")), money("1000"))
+            .leaf("Phone", Fixed(money("80")), money("50"))
+            .leaf("Fun", Flex(1.0), Money::UNLIMITED)
+            .branch("Car", Flex(1.0), |car| car
+                .leaf("Insurance", Fixed(money("120")), Money::UNLIMITED)
+                .leaf("Repairs", FillToMax, money("300")))
+            .build()
+            .unwrap();
+        assert_eq!(root.required_income(), money("1470"));
+        deposit(&mut root, Some("Repairs"), "100");
+        assert_eq!(root.required_income(), money("1370"));
+    }
+
+    #[test]
+    fn inflows_and_account_types_display_briefly() {
+        assert_eq!(Fixed(money("100")).to_string(), "fixed:100.00");
+        assert_eq!(Flex(2.0).to_string(), "flex:2");
+        assert_eq!(Percent(12.5).to_string(), "percent:12.5");
+        assert_eq!(FillToMax.to_string(), "fill");
+        let mut root = sample();
+        assert_eq!(root.data().to_string(), "Branch(2 children)");
+        assert_eq!(root.find_child("Rent").unwrap().data().to_string(), "Leaf");
+        let living = AccountBuilder::new("Living").leaf("Food", Flex(1.0), Money::UNLIMITED).build().unwrap();
+        assert_eq!(living.data().to_string(), "Branch(1 child)");
+    }
+
+    #[test]
+    fn sweeping_moves_every_excess_into_one_account() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("Rent", Fixed(money("100")), money("100"))
+            .leaf("Food", Flex(1.0), money("200"))
+            .leaf("Gym", Flex(1.0), money("50"))
+            .leaf("Savings", Fixed(Money::ZERO), Money::ZERO)
+            .build()
+            .unwrap();
+        deposit(&mut root, Some("Rent"), "130");
+        deposit(&mut root, Some("Food"), "250");
+        deposit(&mut root, Some("Gym"), "40");
+        root.apply(Sweep { to: "Savings".to_owned(), date: date(2020, 1, 31) }).unwrap();
+        assert_eq!(root.balance_of("Rent"), Some(money("100")));
+        assert_eq!(root.balance_of("Food"), Some(money("200")));
+        assert_eq!(root.balance_of("Gym"), Some(money("40")));
+        assert_eq!(root.balance_of("Savings"), Some(money("80")));
+    }
+
+    #[test]
+    fn a_failed_batch_changes_nothing() {
+        let mut root = sample();
+        let actions = vec![
+            Deposit { account: None, amount: money("100"), date: date(2020, 1, 1) },
+            Transfer { from: "Rent".to_owned(), to: Some("Nowhere".to_owned()), amount: money("10"), date: date(2020, 1, 2) },
+            Rename { name: "Fun".to_owned(), new_name: "Games".to_owned() },
+        ];
+        assert_eq!(root.apply_all(actions), Err(BudgetError::AccountNotFound("Nowhere".to_owned())));
+        assert_eq!(root, sample());
+    }
+
+    #[test]
+    fn a_batch_applies_every_action_in_order() {
+        let mut root = sample();
+        let actions = vec![
+            Deposit { account: None, amount: money("100"), date: date(2020, 1, 1) },
+            Transfer { from: "Rent".to_owned(), to: Some("Fun".to_owned()), amount: money("10"), date: date(2020, 1, 2) },
+        ];
+        root.apply_all(actions).unwrap();
+        assert_eq!(root.balance_of("Fun"), Some(money("10")));
+    }
+
+    #[test]
+    fn transfers_to_a_missing_account_keep_the_source() {
+        let mut root = sample();
+        deposit(&mut root, None, "100");
+        let transfer = Transfer { from: "Rent".to_owned(), to: Some("Nowhere".to_owned()), amount: money("40"), date: date(2020, 1, 2) };
+        assert_eq!(root.apply(transfer), Err(BudgetError::AccountNotFound("Nowhere".to_owned())));
+        let transfer = TransferFx { from: "Rent".to_owned(), to: "Nowhere".to_owned(), amount: money("40"), rate: 2.0, date: date(2020, 1, 2) };
+        assert_eq!(root.apply(transfer), Err(BudgetError::AccountNotFound("Nowhere".to_owned())));
+        assert_eq!(root.balance_of("Rent"), Some(money("100")));
+    }
+
+    #[test]
+    fn transfers_converting_to_too_much_keep_the_source() {
+        let mut root = two_currencies();
+        let transfer = TransferFx { from: "Savings".to_owned(), to: "Checking".to_owned(), amount: money("50"), rate: 1e30, date: date(2020, 1, 1) };
+        assert_eq!(root.apply(transfer), Err(BudgetError::InvalidAmount(Money::UNLIMITED)));
+        assert_eq!(root.balance_of("Savings"), Some(money("100")));
+        assert_eq!(root.balance_of("Checking"), Some(money("100")));
+    }
+
+    #[test]
+    fn fixed_inflows_over_the_max_are_warned_about() {
+        let root = AccountBuilder::new("root")
+            .leaf("Phone", Fixed(money("200")), money("100"))
+            .leaf("Rent", Fixed(money("100")), money("100"))
+            .build()
+            .unwrap();
+        assert_eq!(root.funding_warnings(), vec![
+            FundingWarning::FixedOverMax { name: "Phone".to_owned(), fixed: money("200"), max: money("100") }
+        ]);
+    }
+
+    // Rebuilds the Display output from the visitor callbacks
+    struct Printer(String);
+
+    impl Printer {
+        fn line(&mut self, depth: u32, account: &Account, inflow: Option<&Inflow>) {
+            let inflow = inflow.cloned().unwrap_or(Flex(1.0));
+            self.0.push_str(&format!("{:?}:\t{}{}: {}\n", inflow, "  ".repeat(depth as usize), account.name(), account.balance()));
+        }
+    }
+
+    impl Visitor for Printer {
+        fn enter_branch(&mut self, depth: u32, account: &Account, inflow: Option<&Inflow>) {
+            self.line(depth, account, inflow);
+        }
+
+        fn visit_leaf(&mut self, depth: u32, account: &Account, inflow: Option<&Inflow>) {
+            self.line(depth, account, inflow);
+        }
+    }
+
+    #[test]
+    fn walking_visits_accounts_in_display_order() {
+        let mut root = sample();
+        deposit(&mut root, None, "250");
+        let mut printer = Printer(String::new());
+        root.walk(&mut printer);
+        assert_eq!(printer.0, root.to_string());
+    }
+
+    #[test]
+    fn overflow_takes_only_what_wont_fit_anywhere() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("Food", Flex(1.0), money("100"))
+            .leaf("Gym", Fixed(Money::ZERO), money("50"))
+            .leaf("Savings", Fixed(Money::ZERO), Money::ZERO)
+            .overflow("Savings")
+            .build()
+            .unwrap();
+        deposit(&mut root, None, "80");
+        assert_eq!(root.balance_of("Food"), Some(money("80")));
+        deposit(&mut root, None, "120");
+        assert_eq!(root.balance_of("Food"), Some(money("100")));
+        assert_eq!(root.balance_of("Gym"), Some(money("50")));
+        assert_eq!(root.balance_of("Savings"), Some(money("50")));
+    }
+
+    #[test]
+    fn without_overflow_the_rest_is_split_evenly() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("Food", Flex(1.0), money("100"))
+            .leaf("Gym", Fixed(Money::ZERO), money("50"))
+            .leaf("Savings", Fixed(Money::ZERO), Money::ZERO)
+            .build()
+            .unwrap();
+        deposit(&mut root, None, "210");
+        assert_eq!(root.balance_of("Food"), Some(money("120")));
+        assert_eq!(root.balance_of("Gym"), Some(money("70")));
+        assert_eq!(root.balance_of("Savings"), Some(money("20")));
+    }
+
+    #[test]
+    fn overflow_accounts_are_inside_their_branch() {
+        let mut root = sample();
+        let set = SetOverflow { account: "Living".to_owned(), overflow: Some("Fun".to_owned()) };
+        root.apply(set).unwrap();
+        // The overflow has to be somewhere inside the branch
+        let set = SetOverflow { account: "Living".to_owned(), overflow: Some("Rent".to_owned()) };
+        assert_eq!(root.apply(set), Err(BudgetError::AccountNotFound("Rent".to_owned())));
+        let set = SetOverflow { account: "Rent".to_owned(), overflow: None };
+        assert_eq!(root.apply(set), Err(BudgetError::NotBranch("Rent".to_owned())));
+    }
+
+    // root > Savings (200), Fun (10), Card (overdrawn by 30), Loan (overdrawn by 45.50)
+    fn overdrawn() -> Account {
+        let mut root = Account::new_root();
+        root.add_child(leaf("Savings", "200", Money::UNLIMITED), Flex(1.0)).unwrap();
+        root.add_child(leaf("Fun", "10", Money::UNLIMITED), Flex(1.0)).unwrap();
+        for (name, balance) in &[("Card", "-30"), ("Loan", "-45.50")] {
+            let mut account = leaf(name, balance, Money::UNLIMITED);
+            if let Leaf { allow_negative, .. } = &mut account.data {
+                *allow_negative = true;
+            }
+            root.add_child(account, Flex(1.0)).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn covering_negatives_brings_them_back_to_zero() {
+        let mut root = overdrawn();
+        root.cover_negatives("Savings").unwrap();
+        assert_eq!(root.balance_of("Card"), Some(Money::ZERO));
+        assert_eq!(root.balance_of("Loan"), Some(Money::ZERO));
+        assert_eq!(root.balance_of("Fun"), Some(money("10")));
+        assert_eq!(root.balance_of("Savings"), Some(money("124.50")));
+    }
+
+    #[test]
+    fn covering_negatives_needs_enough_in_the_source() {
+        let mut root = overdrawn();
+        assert_eq!(root.cover_negatives("Fun"), Err(BudgetError::InsufficientFunds { available: money("10"), requested: money("75.50") }));
+        assert_eq!(root, overdrawn());
+    }
+
+    #[test]
+    fn deposits_are_split_into_whole_units() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("A", Flex(1.0), Money::UNLIMITED)
+            .leaf("B", Flex(1.0), Money::UNLIMITED)
+            .leaf("C", Flex(1.0), Money::UNLIMITED)
+            .build()
+            .unwrap();
+        root.apply(SetUnit { unit: money("1") }).unwrap();
+        deposit(&mut root, None, "100");
+        assert_eq!(root.balance_of("A"), Some(money("34")));
+        assert_eq!(root.balance_of("B"), Some(money("33")));
+        assert_eq!(root.balance_of("C"), Some(money("33")));
+        assert!(root.iter().all(|account| account.balance().cents() % 100 == 0));
+    }
+
+    #[test]
+    fn splits_by_room_and_evenly_use_the_unit() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("A", Fixed(Money::ZERO), money("10"))
+            .leaf("B", Fixed(Money::ZERO), money("20"))
+            .build()
+            .unwrap();
+        root.apply(SetUnit { unit: money("1") }).unwrap();
+        deposit(&mut root, None, "10");
+        assert_eq!(root.balance_of("A"), Some(money("3")));
+        assert_eq!(root.balance_of("B"), Some(money("7")));
+        deposit(&mut root, None, "25");
+        assert_eq!(root.balance_of("A"), Some(money("13")));
+        assert_eq!(root.balance_of("B"), Some(money("22")));
+    }
+
+    #[test]
+    fn percent_shares_round_down_to_the_unit() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("Savings", Percent(33.0), Money::UNLIMITED)
+            .leaf("Fun", Flex(1.0), Money::UNLIMITED)
+            .build()
+            .unwrap();
+        root.apply(SetUnit { unit: money("1") }).unwrap();
+        deposit(&mut root, None, "1001");
+        assert_eq!(root.balance_of("Savings"), Some(money("330")));
+        assert_eq!(root.balance_of("Fun"), Some(money("671")));
+    }
+
+    #[test]
+    fn capped_deposits_split_into_whole_units() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("A", Fixed(Money::ZERO), money("50"))
+            .leaf("B", Fixed(Money::ZERO), money("50"))
+            .leaf("C", Fixed(Money::ZERO), money("50"))
+            .build()
+            .unwrap();
+        root.apply(SetUnit { unit: money("1") }).unwrap();
+        assert_eq!(root.deposit_capped(money("100")), Money::ZERO);
+        assert_eq!(root.balance_of("A"), Some(money("34")));
+        assert_eq!(root.balance_of("B"), Some(money("33")));
+        assert_eq!(root.balance_of("C"), Some(money("33")));
+    }
+
+    #[test]
+    fn accounts_added_later_take_the_tree_unit() {
+        let mut root = Account::new_root();
+        root.apply(SetUnit { unit: money("1") }).unwrap();
+        let living = AccountBuilder::new("Living").leaf("Food", Flex(1.0), Money::UNLIMITED).build().unwrap();
+        root.add_child(living, Flex(1.0)).unwrap();
+        assert_eq!(root.find_account("Living").unwrap().unit(), money("1"));
+        assert_eq!(root.apply(SetUnit { unit: Money::ZERO }), Err(BudgetError::InvalidAmount(Money::ZERO)));
+    }
+
+    #[test]
+    fn leaf_paths_lists_every_leaf() {
+        let root = sample();
+        assert_eq!(root.leaf_paths(), vec!["root/Rent", "root/Living/Food", "root/Living/Fun"]);
+        assert_eq!(two_foods().leaf_paths(), vec!["root/Home/Food", "root/Work/Food"]);
+    }
+
+    #[test]
+    fn leaf_deposits_refuse_branches() {
+        let mut root = sample();
+        let into_living = DepositLeaf { account: "Living".to_owned(), amount: money("50"), date: date(2020, 1, 1) };
+        assert_eq!(root.apply(into_living), Err(BudgetError::NotLeaf("Living".to_owned())));
+        assert_eq!(root, sample());
+        root.apply(DepositLeaf { account: "Food".to_owned(), amount: money("50"), date: date(2020, 1, 1) }).unwrap();
+        assert_eq!(root.balance_of("Food"), Some(money("50")));
+        assert_eq!(root.balance_of("Fun"), Some(Money::ZERO));
+    }
+
+    #[test]
+    fn same_structure_ignores_balances_and_amounts() {
+        let mut funded = sample();
+        deposit(&mut funded, None, "250");
+        assert!(funded.same_structure(&sample()));
+        let different_rent = AccountBuilder::new("root")
+            .leaf("Rent", Fixed(money("50")), money("50"))
+            .branch("Living", Flex(1.0), |living| living
+                .leaf("Food", Flex(2.0), money("200"))
+                .leaf("Fun", Flex(1.0), Money::UNLIMITED))
+            .build()
+            .unwrap();
+        assert!(different_rent.same_structure(&sample()));
+        let flat = AccountBuilder::new("root")
+            .leaf("Rent", Fixed(money("100")), money("100"))
+            .leaf("Living", Flex(1.0), Money::UNLIMITED)
+            .build()
+            .unwrap();
+        assert!(!flat.same_structure(&sample()));
+    }
+
+    #[test]
+    fn pruning_removes_empty_branches_but_not_the_root() {
+        let mut root = sample();
+        for name in &["Food", "Fun"] {
+            root.apply(Remove { name: (*name).to_owned() }).unwrap();
+        }
+        root.prune_empty();
+        assert!(root.find_account("Living").is_err());
+        assert_eq!(root.leaf_paths(), vec!["root/Rent"]);
+        root.apply(Remove { name: "Rent".to_owned() }).unwrap();
+        root.prune_empty();
+        assert_eq!(root.name, "root");
+        assert!(root.children().is_empty());
+    }
+
+    #[test]
+    fn months_to_goal_counts_deposits_until_the_target() {
+        let trip = with_goal("300", "600", date(2020, 4, 1));
+        assert_eq!(trip.months_to_goal(money("100")), Some(3));
+        // A partial last month still counts
+        assert_eq!(trip.months_to_goal(money("250")), Some(2));
+        assert_eq!(trip.months_to_goal(Money::ZERO), None);
+        assert_eq!(with_goal("600", "600", date(2020, 4, 1)).months_to_goal(Money::ZERO), Some(0));
+        assert_eq!(leaf("Food", "0", Money::UNLIMITED).months_to_goal(money("100")), None);
+    }
+
+    #[test]
+    fn reports_what_went_past_maxes() {
+        let mut root = Account::new_root();
+        root.add_child(leaf("Rent", "0", money("100")), Fixed(money("100"))).unwrap();
+        root.add_child(leaf("Food", "0", money("200")), Flex(1.0)).unwrap();
+        assert_eq!(root.deposit_reporting_excess(money("250")), Money::ZERO);
+        assert_eq!(root.deposit_reporting_excess(money("80")), money("30"));
+        assert_eq!(root.balance(), money("330"));
+    }
+
+    #[test]
+    fn new_accounts_have_their_children_checked() {
+        let mut root = Account::new_root();
+        let mut living = Account::new_root();
+        living.name = "Living".to_owned();
+        if let Branch { children, .. } = &mut living.data {
+            for _ in 0..2 {
+                children.push(BranchEntry { account: leaf("Food", "0", Money::UNLIMITED), inflow: Flex(1.0), priority: 0 });
+            }
         }
+        let new = New {
+            name: "Living".to_owned(),
+            inflow: Flex(1.0),
+            priority: 0,
+            parent: "root".to_owned(),
+            data: living.data,
+            note: None,
+            created: None
+        };
+        assert_eq!(root.apply(new), Err(BudgetError::DuplicateName("Food".to_owned())));
+        assert!(root.children().is_empty());
+    }
+
+    #[test]
+    fn siblings_leave_out_the_account_itself() {
+        let root = sample();
+        let names = |name| root.siblings_of(name).into_iter().map(|account| account.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names("Rent"), vec!["Living"]);
+        assert_eq!(names("Food"), vec!["Fun"]);
+        assert!(names("root").is_empty());
+    }
+
+    #[test]
+    fn zero_weight_children_get_none_of_the_leftovers() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("Food", Flex(1.0), money("100"))
+            .leaf("Spare", Flex(0.0), money("100"))
+            .build()
+            .unwrap();
+        deposit(&mut root, None, "150");
+        assert_eq!(root.balance_of("Food"), Some(money("150")));
+        assert_eq!(root.balance_of("Spare"), Some(Money::ZERO));
+    }
+
+    #[test]
+    fn leftovers_are_split_as_usual_when_every_child_has_zero_weight() {
+        let mut root = AccountBuilder::new("root")
+            .leaf("A", Flex(0.0), money("100"))
+            .leaf("B", Flex(0.0), money("50"))
+            .build()
+            .unwrap();
+        deposit(&mut root, None, "90");
+        assert_eq!(root.balance_of("A"), Some(money("60")));
+        assert_eq!(root.balance_of("B"), Some(money("30")));
+        deposit(&mut root, None, "80");
+        assert_eq!(root.balance_of("A"), Some(money("110")));
+        assert_eq!(root.balance_of("B"), Some(money("60")));
+    }
+
+    fn message(root: &mut Account, action: Action) -> String {
+        root.apply(action).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn creating_under_a_missing_parent_names_both_accounts() {
+        let new = New {
+            name: "Gym".to_owned(),
+            inflow: Flex(1.0),
+            priority: 0,
+            parent: "Nope".to_owned(),
+            data: leaf("Gym", "0", Money::UNLIMITED).data,
+            note: None,
+            created: None
+        };
+        assert_eq!(message(&mut sample(), new), "Could not find parent account Nope to create account Gym");
+    }
+
+    #[test]
+    fn remove_errors_say_what_was_being_removed() {
+        let mut root = sample();
+        assert_eq!(message(&mut root, Remove { name: "root".to_owned() }), "Cannot remove the root account root");
+        assert_eq!(message(&mut root, Remove { name: "Nope".to_owned() }), "Could not find account Nope to remove");
+    }
+
+    #[test]
+    fn rename_errors_say_what_was_being_renamed() {
+        let mut root = sample();
+        let rename = |name: &str, new_name: &str| Rename { name: name.to_owned(), new_name: new_name.to_owned() };
+        assert_eq!(message(&mut root, rename("root", "Top")), "Cannot rename the root account root");
+        assert_eq!(message(&mut root, rename("Nope", "Top")), "Could not find account Nope to rename");
+        assert_eq!(message(&mut root, rename("Food", "Fun")), "Cannot rename Food to Fun: Living already has an account with that name");
+    }
+
+    #[test]
+    fn move_errors_say_what_was_being_moved_where() {
+        let mut root = sample();
+        let to = |name: &str, new_parent: &str| Move { name: name.to_owned(), new_parent: new_parent.to_owned() };
+        assert_eq!(message(&mut root, to("root", "Living")), "Cannot move the root account root");
+        assert_eq!(message(&mut root, to("Nope", "Living")), "Could not find account Nope to move");
+        assert_eq!(message(&mut root, to("Fun", "Rent")), "Cannot move account Fun into leaf account Rent");
+        assert_eq!(message(&mut root, to("Fun", "Nope")), "Could not find account Nope to move Fun into");
+    }
+
+    #[test]
+    fn withdraw_and_deposit_errors_name_the_missing_account() {
+        let mut root = sample();
+        let withdraw = Withdraw { account: "Nope".to_owned(), amount: money("5"), date: date(2020, 1, 1) };
+        assert_eq!(message(&mut root, withdraw), "Could not find parent account Nope to withdraw from");
+        let deposit = Deposit { account: Some("Nope".to_owned()), amount: money("5"), date: date(2020, 1, 1) };
+        assert_eq!(message(&mut root, deposit), "Could not find parent account Nope to deposit to");
     }
 }
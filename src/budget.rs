@@ -0,0 +1,101 @@
+use crate::{
+    account::{Account, Action},
+    chrono::naive::NaiveDate,
+    error::BudgetError,
+    ledger::{Ledger, Transaction},
+    money::Money,
+};
+
+/// The top level of a budget: an account tree with its history, and the settings that apply to all of it
+#[derive(Clone, Debug)]
+pub struct Budget {
+    ledger: Ledger,
+    // An ISO 4217 code like USD
+    currency: String
+}
+
+impl Budget {
+    pub fn new(currency: &str) -> Budget {
+        Budget {
+            ledger: Ledger::new(Account::new_root()),
+            currency: currency.to_owned()
+        }
+    }
+
+    pub fn apply(&mut self, action: Action) -> Result<(), BudgetError> {
+        self.ledger.apply(action)
+    }
+
+    pub fn undo(&mut self) -> Result<(), BudgetError> {
+        self.ledger.undo()
+    }
+
+    pub fn schedule(&mut self, action: Action) -> Result<(), BudgetError> {
+        self.ledger.schedule(action)
+    }
+
+    pub fn advance_to(&mut self, today: NaiveDate) -> Result<(), BudgetError> {
+        self.ledger.advance_to(today)
+    }
+
+    pub fn balance_of(&self, name: &str) -> Option<Money> {
+        self.root().balance_of(name)
+    }
+
+    pub fn root(&self) -> &Account {
+        self.ledger.root()
+    }
+
+    pub fn history(&self) -> &[Transaction] {
+        self.ledger.history()
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    pub fn unit(&self) -> Money {
+        self.root().unit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::account::{AccountType, Inflow, Rollover}};
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn actions_go_through_to_the_tree_and_its_history() {
+        let mut budget = Budget::new("EUR");
+        let new = Action::New {
+            name: "Food".to_owned(),
+            inflow: Inflow::Flex(1.0),
+            priority: 0,
+            parent: "root".to_owned(),
+            data: AccountType::Leaf {
+                balance: Money::ZERO,
+                max: Money::UNLIMITED,
+                allow_negative: false,
+                rollover: Rollover::Keep,
+                goal: None,
+                currency: None
+            },
+            note: None,
+            created: None
+        };
+        budget.apply(new).unwrap();
+        budget.apply(Action::Deposit { account: None, amount: Money::from_cents(500), date: date(2020, 1, 1) }).unwrap();
+        assert_eq!(budget.balance_of("Food"), Some(Money::from_cents(500)));
+        assert_eq!(budget.currency(), "EUR");
+        assert_eq!(budget.history().len(), 1);
+        budget.undo().unwrap();
+        assert_eq!(budget.balance_of("Food"), Some(Money::ZERO));
+    }
+}
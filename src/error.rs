@@ -0,0 +1,87 @@
+use {
+    crate::money::Money,
+    std::{error::Error, fmt},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BudgetError {
+    AccountNotFound(String),
+    AddChildToLeaf,
+    InsufficientFunds { available: Money, requested: Money },
+    DuplicateName(String),
+    PercentOver100(f64),
+    RootAccount { action: &'static str, name: String },
+    HasChildren(String),
+    MoveIntoSelf { name: String, new_parent: String },
+    ParentNotFound { parent: String, name: String },
+    NotFoundTo { name: String, action: &'static str },
+    // Withdrawals and deposits have always called their target a parent account
+    TargetNotFound { name: String, action: &'static str },
+    MoveIntoLeaf { name: String, new_parent: String },
+    MoveTargetNotFound { name: String, new_parent: String },
+    RenameTaken { name: String, new_name: String, parent: String },
+    DiffMismatch,
+    NothingToUndo,
+    MalformedCsv { line: usize, reason: String },
+    InvalidAmount(Money),
+    InvalidWeight(f64),
+    BranchMax(String),
+    AmbiguousName(String),
+    InvalidRate(f64),
+    MixedCurrencies(String),
+    NotLeaf(String),
+    NotBranch(String),
+    Frozen(String),
+    Archived(String),
+}
+
+impl fmt::Display for BudgetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::BudgetError::*;
+        match self {
+            AccountNotFound(name) => write!(f, "Could not find account {}", name),
+            AddChildToLeaf => write!(f, "Cannot add a child to a leaf account"),
+            InsufficientFunds { available, requested } =>
+                write!(f, "Cannot withdraw {} when only {} is available, {} short", requested, available, *requested - *available),
+            DuplicateName(name) => write!(f, "An account named {} already exists there", name),
+            PercentOver100(total) => write!(f, "Percent inflows into one account cannot add up to more than 100%, found {}%", total),
+            RootAccount { action, name } => write!(f, "Cannot {} the root account {}", action, name),
+            HasChildren(name) => write!(f, "Cannot remove account {} while it still has child accounts", name),
+            MoveIntoSelf { name, new_parent } =>
+                write!(f, "Cannot move account {} into {}, which is inside it", name, new_parent),
+            ParentNotFound { parent, name } => write!(f, "Could not find parent account {} to create account {}", parent, name),
+            NotFoundTo { name, action } => write!(f, "Could not find account {} to {}", name, action),
+            TargetNotFound { name, action } => write!(f, "Could not find parent account {} to {}", name, action),
+            MoveIntoLeaf { name, new_parent } => write!(f, "Cannot move account {} into leaf account {}", name, new_parent),
+            MoveTargetNotFound { name, new_parent } => write!(f, "Could not find account {} to move {} into", new_parent, name),
+            RenameTaken { name, new_name, parent } =>
+                write!(f, "Cannot rename {} to {}: {} already has an account with that name", name, new_name, parent),
+            DiffMismatch => write!(f, "Tried to diff accounts of different types"),
+            NothingToUndo => write!(f, "There is nothing to undo"),
+            MalformedCsv { line, reason } => write!(f, "Malformed CSV row at line {}: {}", line, reason),
+            InvalidAmount(amount) => write!(f, "Expected a limited amount of money that isn't negative, found {}", amount),
+            InvalidWeight(weight) => write!(f, "Inflow weights must be finite and not negative, found {}", weight),
+            AmbiguousName(name) =>
+                write!(f, "More than one account is named {}, use a path like root/Parent/{} instead", name, name),
+            InvalidRate(rate) => write!(f, "Exchange rates must be finite and above zero, found {}", rate),
+            MixedCurrencies(name) => write!(f, "Cannot add up the balance of {}, which holds more than one currency", name),
+            NotLeaf(name) => write!(f, "Expected {} to be a leaf account, but it has child accounts", name),
+            NotBranch(name) => write!(f, "Expected {} to be a branch account, but it's a leaf", name),
+            Frozen(name) => write!(f, "Cannot take money out of {}, which is frozen", name),
+            Archived(name) => write!(f, "Cannot deposit into {}, which is archived", name),
+            BranchMax(name) => write!(f, "Cannot set the max of {}, a branch's max comes from its children", name),
+        }
+    }
+}
+
+impl Error for BudgetError {}
+
+impl BudgetError {
+    // Swap a missing account for an error that says what it was needed for, leaving any other error as it is
+    pub(crate) fn when_not_found(self, error: impl FnOnce(String) -> BudgetError) -> BudgetError {
+        match self {
+            BudgetError::AccountNotFound(name) => error(name),
+            other => other
+        }
+    }
+}
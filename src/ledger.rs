@@ -0,0 +1,431 @@
+use crate::{
+    account::{Account, AccountDiff, Action, BranchEntry},
+    chrono::{naive::NaiveDate, Datelike},
+    error::BudgetError,
+    money::Money,
+};
+
+/// A record of money moving into, out of, or around the budget, naming accounts by their full paths like
+/// root/Living/Food
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transaction {
+    Withdraw { account: String, amount: Money, date: NaiveDate },
+    Deposit { account: String, amount: Money, date: NaiveDate },
+    Transfer { from: String, to: String, amount: Money, date: NaiveDate }
+}
+
+/// How much went into and out of a category through transactions, next to how much it can hold
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CategorySummary {
+    pub deposited: Money,
+    pub withdrawn: Money,
+    pub net: Money,
+    pub max: Money
+}
+
+/// An account tree along with the history of every transaction applied to it
+#[derive(Clone, Debug)]
+pub struct Ledger {
+    root: Account,
+    transactions: Vec<Transaction>,
+    // The tree and history length from before each applied action, most recent last
+    snapshots: Vec<(Account, usize)>,
+    // Dated actions waiting to be applied, sorted by date
+    pending: Vec<Action>,
+    today: Option<NaiveDate>
+}
+
+impl Transaction {
+    // Deposits and transfers without a destination go to the root, so they're recorded against it
+    //
+    // Some amounts, like how much emptying an account withdraws, aren't known until the action is applied, so this
+    // looks at the tree from both before and after. Paths come from after, which has every account the action named,
+    // including any it created.
+    fn from_action(before: &Account, after: &Account, action: &Action) -> Vec<Transaction> {
+        let path = |account: &str| after.resolve_path(account).unwrap_or_else(|_| account.to_owned());
+        let or_root = |account: &Option<String>| path(account.as_deref().unwrap_or_else(|| after.name()));
+        // Includes archived leaves, which can still be emptied even though they're left out of the root's balance
+        let total = |tree: &Account| -> Money { tree.iter_all().filter(|account| account.is_leaf()).map(Account::balance).sum() };
+        match action {
+            Action::Withdraw { account, amount, date } =>
+                vec![Transaction::Withdraw { account: path(account), amount: *amount, date: *date }],
+            Action::Empty { account, date } =>
+                vec![Transaction::Withdraw { account: path(account), amount: total(before) - total(after), date: *date }],
+            Action::Deposit { account, amount, date } =>
+                vec![Transaction::Deposit { account: or_root(account), amount: *amount, date: *date }],
+            Action::DepositLeaf { account, amount, date } =>
+                vec![Transaction::Deposit { account: path(account), amount: *amount, date: *date }],
+            Action::MultiDeposit { entries, date } => entries.iter()
+                .map(|(account, amount)| Transaction::Deposit { account: path(account), amount: *amount, date: *date })
+                .collect(),
+            Action::Transfer { from, to, amount, date } =>
+                vec![Transaction::Transfer { from: path(from), to: or_root(to), amount: *amount, date: *date }],
+            // The two sides are in different currencies, so they can't be one transfer of a single amount
+            Action::TransferFx { from, to, amount, rate, date } => vec![
+                Transaction::Withdraw { account: path(from), amount: *amount, date: *date },
+                Transaction::Deposit { account: path(to), amount: amount.convert(*rate), date: *date }
+            ],
+            // Each leaf that was swept gave up some of its balance to the destination
+            Action::Sweep { to, date } => after.changes_since(before).into_iter()
+                .filter_map(|change| match change {
+                    AccountDiff::Changed { path: from, before, after } if after < before =>
+                        Some(Transaction::Transfer { from, to: path(to), amount: before - after, date: *date }),
+                    _ => None
+                })
+                .collect(),
+            Action::TransferToNew { from, to, amount, date, .. } =>
+                vec![Transaction::Transfer { from: path(from), to: path(to), amount: *amount, date: *date }],
+            _ => Vec::new()
+        }
+    }
+
+    pub fn amount(&self) -> Money {
+        match self {
+            Transaction::Withdraw { amount, .. }
+                | Transaction::Deposit { amount, .. }
+                | Transaction::Transfer { amount, .. } => *amount
+        }
+    }
+
+    /// How much this transaction added to or, if negative, took from the account at the path, like root/Living/Food
+    pub fn change_for(&self, path: &str) -> Money {
+        match self {
+            Transaction::Withdraw { account, amount, .. } if account == path => -*amount,
+            Transaction::Deposit { account, amount, .. } if account == path => *amount,
+            Transaction::Transfer { from, to, .. } if from == path && to == path => Money::ZERO,
+            Transaction::Transfer { from, amount, .. } if from == path => -*amount,
+            Transaction::Transfer { to, amount, .. } if to == path => *amount,
+            _ => Money::ZERO
+        }
+    }
+
+    pub fn date(&self) -> NaiveDate {
+        match self {
+            Transaction::Withdraw { date, .. }
+                | Transaction::Deposit { date, .. }
+                | Transaction::Transfer { date, .. } => *date
+        }
+    }
+}
+
+impl Ledger {
+    pub fn new(root: Account) -> Ledger {
+        Ledger {
+            root,
+            transactions: Vec::new(),
+            snapshots: Vec::new(),
+            pending: Vec::new(),
+            today: None
+        }
+    }
+
+    pub fn apply(&mut self, action: Action) -> Result<(), BudgetError> {
+        let snapshot = self.root.clone();
+        self.root.apply(action.clone())?;
+        let transactions = Transaction::from_action(&snapshot, &self.root, &action);
+        self.snapshots.push((snapshot, self.transactions.len()));
+        self.transactions.extend(transactions);
+        Ok(())
+    }
+
+    /// Reverse the most recently applied action, including any structural changes it made
+    pub fn undo(&mut self) -> Result<(), BudgetError> {
+        let (root, length) = self.snapshots.pop().ok_or(BudgetError::NothingToUndo)?;
+        self.root = root;
+        self.transactions.truncate(length);
+        Ok(())
+    }
+
+    /// Queue an action to be applied once the ledger advances to its date
+    ///
+    /// Actions without a date, or dated on or before the day the ledger has advanced to, are applied immediately.
+    pub fn schedule(&mut self, action: Action) -> Result<(), BudgetError> {
+        match action.date() {
+            Some(date) if self.today.is_none_or(|today| date > today) => {
+                let index = self.pending.iter()
+                    .position(|pending| pending.date() > Some(date))
+                    .unwrap_or(self.pending.len());
+                self.pending.insert(index, action);
+                Ok(())
+            }
+            _ => self.apply(action)
+        }
+    }
+
+    /// Apply every scheduled action dated on or before the given day, in date order
+    ///
+    /// An action that fails stops the rest and stays pending, so it's tried again on the next advance.
+    pub fn advance_to(&mut self, today: NaiveDate) -> Result<(), BudgetError> {
+        self.today = Some(today);
+        while self.pending.first().and_then(Action::date).is_some_and(|date| date <= today) {
+            self.apply(self.pending[0].clone())?;
+            self.pending.remove(0);
+        }
+        Ok(())
+    }
+
+    pub fn pending(&self) -> &[Action] {
+        &self.pending
+    }
+
+    // The path transactions were recorded under for the named account, which may have been removed since
+    fn path_of(&self, name: &str) -> String {
+        self.root.resolve_path(name).unwrap_or_else(|_| name.to_owned())
+    }
+
+    /// Every transaction involving the account, including transfers on either side, oldest first
+    ///
+    /// The account can be named the same ways actions name it, by a unique name or a path.
+    pub fn transactions_for(&self, name: &str) -> Vec<&Transaction> {
+        let path = self.path_of(name);
+        self.transactions.iter()
+            .filter(|transaction| match transaction {
+                Transaction::Withdraw { account, .. } | Transaction::Deposit { account, .. } => *account == path,
+                Transaction::Transfer { from, to, .. } => *from == path || *to == path
+            })
+            .collect()
+    }
+
+    /// The average put into the named account each calendar month, through deposits and transfers in
+    ///
+    /// Months count from the first transaction naming the account to the last, including any without a deposit, and
+    /// an account with no transactions averages nothing.
+    pub fn average_monthly_deposit(&self, name: &str) -> Money {
+        let path = self.path_of(name);
+        let transactions = self.transactions_for(name);
+        let month = |transaction: &&Transaction| transaction.date().year() as i64 * 12 + transaction.date().month() as i64;
+        let (first, last) = match (transactions.iter().map(month).min(), transactions.iter().map(month).max()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Money::ZERO
+        };
+        let deposited: Money = transactions.iter()
+            .map(|transaction| match transaction {
+                Transaction::Deposit { amount, .. } => *amount,
+                Transaction::Transfer { from, amount, .. } if *from != path => *amount,
+                _ => Money::ZERO
+            })
+            .sum();
+        deposited / (last - first + 1)
+    }
+
+    /// The total amount the named account gained through transactions between the two dates, inclusive
+    pub fn net_change(&self, account: &str, from: NaiveDate, to: NaiveDate) -> Money {
+        let path = self.path_of(account);
+        self.transactions.iter()
+            .filter(|transaction| transaction.date() >= from && transaction.date() <= to)
+            .map(|transaction| transaction.change_for(&path))
+            .sum()
+    }
+
+    /// Total up the transactions naming the account or any of its descendants
+    ///
+    /// Transfers between two accounts inside the category don't count on either side.
+    pub fn category_summary(&self, name: &str) -> Option<CategorySummary> {
+        let path = self.root.resolve_path(name).ok()?;
+        let category = path.split('/').skip(1).try_fold(&self.root, |account, name| {
+            account.children().iter().map(BranchEntry::account).find(|child| child.name() == name)
+        })?;
+        let below = format!("{}/", path);
+        let inside = |account: &String| *account == path || account.starts_with(&below);
+        let (deposited, withdrawn) = self.transactions.iter()
+            .fold((Money::ZERO, Money::ZERO), |(deposited, withdrawn), transaction| match transaction {
+                Transaction::Deposit { account, amount, .. } if inside(account) => (deposited + *amount, withdrawn),
+                Transaction::Withdraw { account, amount, .. } if inside(account) => (deposited, withdrawn + *amount),
+                Transaction::Transfer { from, to, .. } if inside(from) && inside(to) => (deposited, withdrawn),
+                Transaction::Transfer { to, amount, .. } if inside(to) => (deposited + *amount, withdrawn),
+                Transaction::Transfer { from, amount, .. } if inside(from) => (deposited, withdrawn + *amount),
+                _ => (deposited, withdrawn)
+            });
+        Some(CategorySummary { deposited, withdrawn, net: deposited - withdrawn, max: category.max() })
+    }
+
+    pub fn root(&self) -> &Account {
+        &self.root
+    }
+
+    pub fn history(&self) -> &[Transaction] {
+        &self.transactions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::account::{AccountBuilder, Inflow::*}};
+
+    fn money(amount: &str) -> Money {
+        amount.parse().unwrap()
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    // root > Rent (fixed 100, max 100), Food (flex 1, max 200), Fun (flex 1)
+    fn sample() -> Account {
+        AccountBuilder::new("root")
+            .leaf("Rent", Fixed(money("100")), money("100"))
+            .leaf("Food", Flex(1.0), money("200"))
+            .leaf("Fun", Flex(1.0), Money::UNLIMITED)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn applied_actions_are_recorded_with_their_dates_and_amounts() {
+        let mut ledger = Ledger::new(sample());
+        ledger.apply(Action::Deposit { account: None, amount: money("300"), date: date(2020, 1, 1) }).unwrap();
+        ledger.apply(Action::Withdraw { account: "Food".to_owned(), amount: money("20"), date: date(2020, 1, 5) }).unwrap();
+        ledger.apply(Action::Transfer { from: "Fun".to_owned(), to: Some("Food".to_owned()), amount: money("5"), date: date(2020, 1, 9) }).unwrap();
+        ledger.apply(Action::Rename { name: "Fun".to_owned(), new_name: "Games".to_owned() }).unwrap();
+        assert_eq!(ledger.history(), &[
+            Transaction::Deposit { account: "root".to_owned(), amount: money("300"), date: date(2020, 1, 1) },
+            Transaction::Withdraw { account: "root/Food".to_owned(), amount: money("20"), date: date(2020, 1, 5) },
+            Transaction::Transfer { from: "root/Fun".to_owned(), to: "root/Food".to_owned(), amount: money("5"), date: date(2020, 1, 9) }
+        ][..]);
+    }
+
+    #[test]
+    fn undo_puts_back_a_deposit_spread_over_several_children() {
+        let mut ledger = Ledger::new(sample());
+        ledger.apply(Action::Deposit { account: None, amount: money("50"), date: date(2020, 1, 1) }).unwrap();
+        let before = ledger.root().clone();
+        ledger.apply(Action::Deposit { account: None, amount: money("250"), date: date(2020, 1, 2) }).unwrap();
+        assert_ne!(ledger.root(), &before);
+        ledger.undo().unwrap();
+        assert_eq!(ledger.root(), &before);
+        assert_eq!(ledger.history().len(), 1);
+    }
+
+    #[test]
+    fn undo_reverses_withdrawals_transfers_and_structure() {
+        let mut ledger = Ledger::new(sample());
+        ledger.apply(Action::Deposit { account: None, amount: money("300"), date: date(2020, 1, 1) }).unwrap();
+        let funded = ledger.root().clone();
+        ledger.apply(Action::Withdraw { account: "Food".to_owned(), amount: money("20"), date: date(2020, 1, 2) }).unwrap();
+        ledger.apply(Action::Transfer { from: "Fun".to_owned(), to: Some("Rent".to_owned()), amount: money("5"), date: date(2020, 1, 3) }).unwrap();
+        ledger.apply(Action::Remove { name: "Fun".to_owned() }).unwrap();
+        for _ in 0..3 {
+            ledger.undo().unwrap();
+        }
+        assert_eq!(ledger.root(), &funded);
+        assert_eq!(ledger.history().len(), 1);
+    }
+
+    #[test]
+    fn undo_with_nothing_applied_is_an_error() {
+        let mut ledger = Ledger::new(sample());
+        assert_eq!(ledger.undo(), Err(BudgetError::NothingToUndo));
+    }
+
+    #[test]
+    fn scheduled_actions_wait_until_their_date() {
+        let mut ledger = Ledger::new(sample());
+        ledger.advance_to(date(2020, 1, 15)).unwrap();
+        ledger.schedule(Action::Deposit { account: Some("Fun".to_owned()), amount: money("30"), date: date(2020, 2, 1) }).unwrap();
+        ledger.schedule(Action::Deposit { account: Some("Fun".to_owned()), amount: money("10"), date: date(2020, 1, 20) }).unwrap();
+        assert_eq!(ledger.pending().len(), 2);
+        assert_eq!(ledger.pending()[0].date(), Some(date(2020, 1, 20)));
+        assert_eq!(ledger.root().balance_of("Fun"), Some(Money::ZERO));
+
+        ledger.advance_to(date(2020, 1, 31)).unwrap();
+        assert_eq!(ledger.root().balance_of("Fun"), Some(money("10")));
+        ledger.advance_to(date(2020, 2, 2)).unwrap();
+        assert_eq!(ledger.root().balance_of("Fun"), Some(money("40")));
+        assert!(ledger.pending().is_empty());
+    }
+
+    #[test]
+    fn actions_dated_before_today_apply_immediately() {
+        let mut ledger = Ledger::new(sample());
+        ledger.advance_to(date(2020, 3, 1)).unwrap();
+        ledger.schedule(Action::Deposit { account: Some("Fun".to_owned()), amount: money("10"), date: date(2020, 2, 1) }).unwrap();
+        assert!(ledger.pending().is_empty());
+        assert_eq!(ledger.root().balance_of("Fun"), Some(money("10")));
+    }
+
+    #[test]
+    fn net_change_sums_transactions_in_the_range() {
+        let mut ledger = Ledger::new(sample());
+        ledger.apply(Action::Deposit { account: Some("Fun".to_owned()), amount: money("100"), date: date(2020, 1, 1) }).unwrap();
+        ledger.apply(Action::Withdraw { account: "Fun".to_owned(), amount: money("30"), date: date(2020, 1, 15) }).unwrap();
+        ledger.apply(Action::Transfer { from: "Fun".to_owned(), to: Some("Food".to_owned()), amount: money("20"), date: date(2020, 1, 31) }).unwrap();
+        ledger.apply(Action::Withdraw { account: "Fun".to_owned(), amount: money("5"), date: date(2020, 2, 1) }).unwrap();
+        assert_eq!(ledger.net_change("Fun", date(2020, 1, 15), date(2020, 1, 31)), money("-50"));
+        assert_eq!(ledger.net_change("Food", date(2020, 1, 15), date(2020, 1, 31)), money("20"));
+        assert_eq!(ledger.net_change("Fun", date(2020, 1, 1), date(2020, 12, 31)), money("45"));
+        assert_eq!(ledger.net_change("Fun", date(2020, 3, 1), date(2020, 3, 31)), Money::ZERO);
+    }
+
+    #[test]
+    fn category_summaries_total_a_category_and_its_descendants() {
+        let root = AccountBuilder::new("root")
+            .leaf("Rent", Fixed(money("100")), money("100"))
+            .branch("Living", Flex(1.0), |living| living
+                .leaf("Food", Flex(1.0), money("200"))
+                .leaf("Fun", Flex(1.0), Money::UNLIMITED))
+            .build()
+            .unwrap();
+        let mut ledger = Ledger::new(root);
+        let to = |account: &str| Some(account.to_owned());
+        ledger.apply(Action::Deposit { account: to("Rent"), amount: money("100"), date: date(2020, 1, 1) }).unwrap();
+        ledger.apply(Action::Deposit { account: to("Food"), amount: money("50"), date: date(2020, 1, 1) }).unwrap();
+        ledger.apply(Action::Deposit { account: to("Fun"), amount: money("30"), date: date(2020, 1, 1) }).unwrap();
+        ledger.apply(Action::Withdraw { account: "Food".to_owned(), amount: money("20"), date: date(2020, 1, 2) }).unwrap();
+        ledger.apply(Action::Transfer { from: "Fun".to_owned(), to: to("Food"), amount: money("5"), date: date(2020, 1, 3) }).unwrap();
+        ledger.apply(Action::Transfer { from: "Rent".to_owned(), to: to("Fun"), amount: money("10"), date: date(2020, 1, 4) }).unwrap();
+        assert_eq!(ledger.category_summary("Food"), Some(CategorySummary {
+            deposited: money("55"), withdrawn: money("20"), net: money("35"), max: money("200")
+        }));
+        // The transfer from Fun to Food stays inside Living, so it isn't counted
+        assert_eq!(ledger.category_summary("Living"), Some(CategorySummary {
+            deposited: money("90"), withdrawn: money("20"), net: money("70"), max: Money::UNLIMITED
+        }));
+        assert_eq!(ledger.category_summary("Nowhere"), None);
+    }
+
+    #[test]
+    fn transactions_for_lists_only_the_accounts_own() {
+        let mut ledger = Ledger::new(sample());
+        ledger.apply(Action::Deposit { account: Some("root/Fun".to_owned()), amount: money("100"), date: date(2020, 1, 1) }).unwrap();
+        ledger.apply(Action::Deposit { account: Some("Rent".to_owned()), amount: money("100"), date: date(2020, 1, 1) }).unwrap();
+        ledger.apply(Action::Withdraw { account: "Fun".to_owned(), amount: money("30"), date: date(2020, 1, 2) }).unwrap();
+        ledger.apply(Action::Transfer { from: "Rent".to_owned(), to: Some("Food".to_owned()), amount: money("10"), date: date(2020, 1, 3) }).unwrap();
+        ledger.apply(Action::Transfer { from: "Fun".to_owned(), to: Some("root/Food".to_owned()), amount: money("5"), date: date(2020, 1, 4) }).unwrap();
+        assert_eq!(ledger.transactions_for("Fun"), vec![
+            &Transaction::Deposit { account: "root/Fun".to_owned(), amount: money("100"), date: date(2020, 1, 1) },
+            &Transaction::Withdraw { account: "root/Fun".to_owned(), amount: money("30"), date: date(2020, 1, 2) },
+            &Transaction::Transfer { from: "root/Fun".to_owned(), to: "root/Food".to_owned(), amount: money("5"), date: date(2020, 1, 4) },
+        ]);
+        assert_eq!(ledger.transactions_for("root/Food").len(), 2);
+        assert_eq!(ledger.net_change("root/Fun", date(2020, 1, 1), date(2020, 1, 31)), money("65"));
+        assert_eq!(ledger.transactions_for("Nowhere"), Vec::<&Transaction>::new());
+    }
+
+    #[test]
+    fn monthly_deposits_average_over_every_active_month() {
+        let mut ledger = Ledger::new(sample());
+        assert_eq!(ledger.average_monthly_deposit("Fun"), Money::ZERO);
+        ledger.apply(Action::Deposit { account: Some("Fun".to_owned()), amount: money("100"), date: date(2020, 1, 5) }).unwrap();
+        assert_eq!(ledger.average_monthly_deposit("Fun"), money("100"));
+        ledger.apply(Action::Deposit { account: Some("Fun".to_owned()), amount: money("50"), date: date(2020, 1, 20) }).unwrap();
+        ledger.apply(Action::Withdraw { account: "Fun".to_owned(), amount: money("40"), date: date(2020, 2, 3) }).unwrap();
+        ledger.apply(Action::Transfer { from: "Food".to_owned(), to: Some("Fun".to_owned()), amount: Money::ZERO, date: date(2020, 3, 1) }).unwrap();
+        ledger.apply(Action::Transfer { from: "Fun".to_owned(), to: Some("Food".to_owned()), amount: money("10"), date: date(2020, 3, 2) }).unwrap();
+        // 150 deposited over January through March
+        assert_eq!(ledger.average_monthly_deposit("Fun"), money("50"));
+    }
+
+    #[test]
+    fn scheduled_actions_that_fail_stay_pending() {
+        let mut ledger = Ledger::new(sample());
+        ledger.advance_to(date(2020, 1, 15)).unwrap();
+        ledger.schedule(Action::Withdraw { account: "Fun".to_owned(), amount: money("50"), date: date(2020, 2, 1) }).unwrap();
+        assert_eq!(ledger.advance_to(date(2020, 2, 2)), Err(BudgetError::InsufficientFunds { available: Money::ZERO, requested: money("50") }));
+        assert_eq!(ledger.pending().len(), 1);
+
+        ledger.apply(Action::Deposit { account: Some("Fun".to_owned()), amount: money("80"), date: date(2020, 2, 2) }).unwrap();
+        ledger.advance_to(date(2020, 2, 3)).unwrap();
+        assert_eq!(ledger.root().balance_of("Fun"), Some(money("30")));
+        assert!(ledger.pending().is_empty());
+    }
+}
@@ -0,0 +1,12 @@
+extern crate chrono;
+extern crate regex;
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
+
+pub mod account;
+pub mod budget;
+pub mod error;
+pub mod ledger;
+pub mod money;
+pub mod parser;
@@ -1,45 +1,93 @@
+extern crate budgeting;
 extern crate clap;
 extern crate chrono;
-extern crate regex;
 
-mod account;
-mod parser;
 use crate::{
-    account::{Account, Action::{self, *}},
+    budgeting::{
+        account::{Account, Action},
+        parser::{parse, parse_action, parse_date},
+    },
     chrono::NaiveDate,
     clap::App,
-    parser::{parse, parse_date},
 };
 use std::{
     fmt::Display,
     fs::File,
-    io::Read,
+    io::{self, BufRead, Read, Write},
     iter::once,
+    path::Path,
 };
 
 fn err_to_str<T>(result: Result<T, impl Display>) -> Result<T, String> {
     result.map_err(|e| format!("{}", e))
 }
 
-fn calculate(actions: &Vec<Action>, start: Option<NaiveDate>, end: Option<NaiveDate>) -> Result<Account, String> {
-    let mut account = Account::new_root();
+fn calculate(initial: &Account, actions: &Vec<Action>, start: Option<NaiveDate>, end: Option<NaiveDate>) -> Result<Account, String> {
+    let mut account = initial.clone();
     for action in actions {
-        match &action {
-            &Deposit { date, .. } | &Withdraw { date, .. } => {
-                match &start {
-                    Some(start) if date < start => continue,
-                    _ => ()
-                }
-                match &end {
-                    Some(end) if date > end => continue,
-                    _ => ()
-                }
+        // Actions without a date, like adding an account, always apply
+        if let Some(date) = action.date() {
+            match &start {
+                Some(start) if date < *start => continue,
+                _ => ()
             }
-            _ => ()
+            match &end {
+                Some(end) if date > *end => continue,
+                _ => ()
+            }
+        }
+        err_to_str(account.apply(action.clone()))?;
+    }
+
+    Ok(account)
+}
+
+fn run_file(path: &str, initial: &Account, until: Option<NaiveDate>, diff: Option<NaiveDate>) -> Result<Account, String> {
+    let mut file = err_to_str(File::open(path))?;
+    let mut contents = String::new();
+    err_to_str(file.read_to_string(&mut contents))?;
+
+    let actions = parse(&contents).map_err(|e| e.join("\n"))?;
+
+    let account = calculate(initial, &actions, None, until)?;
+    if let Some(start) = diff {
+        let before = calculate(initial, &actions, None, Some(start))?;
+        println!("The difference is:\n{}", err_to_str(account.diff(&before))?);
+    } else {
+        match until {
+            Some(until) => println!("The budget as of {}:\n{}", until, account),
+            None => println!("The current budget:\n{}", account)
         }
-        account.apply(action.clone())?;
     }
+    Ok(account)
+}
 
+// Apply commands from stdin one at a time, printing the budget after each, until quit or the end of input
+fn repl(mut account: Account) -> Result<Account, String> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        err_to_str(io::stdout().flush())?;
+        let line = match lines.next() {
+            Some(line) => err_to_str(line)?,
+            None => break
+        };
+        let mut words = line.split_whitespace();
+        let result = match (words.next(), words.next()) {
+            (None, _) => continue,
+            (Some("quit"), None) => break,
+            (Some("save"), Some(saved)) => account.save_to_file(Path::new(saved))
+                .map_err(|e| format!("Could not save the budget to {}: {}", saved, e)),
+            (Some("load"), Some(saved)) => Account::load_from_file(Path::new(saved))
+                .map(|loaded| account = loaded),
+            _ => parse_action(&line).and_then(|action| err_to_str(account.apply(action)))
+        };
+        match result {
+            Ok(()) => println!("{}", account),
+            Err(e) => println!("{}", e)
+        }
+    }
     Ok(account)
 }
 
@@ -48,12 +96,15 @@ fn main() -> Result<(), String> {
         .version("0.1.0")
         .author("Ryan Goldstein")
         .about("Manage your money through space and time")
-        .args_from_usage("<FILE>    'The file to read the budget from'
+        .args_from_usage("[FILE]    'The file to read the budget from'
+                         -i,--interactive 'Enter commands one at a time after reading the file'
                          -d,--diff=[DATE] 'The date to diff from'
-                         -u,--until=[DATE] 'The date to go until'")
+                         -u,--until=[DATE] 'The date to go until'
+                         -l,--load=[SAVED] 'A saved budget to start from'
+                         -s,--save=[SAVED] 'Where to save the resulting budget'
+                         -c,--csv=[CSV] 'Where to export the resulting leaf balances as CSV'")
         .get_matches();
 
-    let path = err_to_str(matches.value_of("FILE").ok_or("Missing required paramter file to read the budget from"))?;
     let until = matches.value_of("until")
         .map(|date| parse_date(0, &mut once(date)))
         .map_or(Ok(None), |date| date.map(Some))?;
@@ -61,22 +112,24 @@ fn main() -> Result<(), String> {
         .map(|date| parse_date(0, &mut once(date)))
         .map_or(Ok(None), |date| date.map(Some))?;
 
-    let mut file = err_to_str(File::open(path))?;
-    let mut contents = String::new();
-    err_to_str(file.read_to_string(&mut contents))?;
+    let initial = match matches.value_of("load") {
+        Some(saved) => Account::load_from_file(Path::new(saved))?,
+        None => Account::new_root()
+    };
 
-    let actions = parse(&contents).map_err(|e| e.join("\n"))?;
-
-    if let Some(start) = diff {
-        let before = calculate(&actions, None, Some(start))?;
-        let after = calculate(&actions, None, until)?;
-        println!("The difference is:\n{}", after.diff(&before)?);
-    } else {
-        let account = calculate(&actions, None, until)?;
-        match until {
-            Some(until) => println!("The budget as of {}:\n{}", until, account),
-            None => println!("The current budget:\n{}", account)
-        }
+    let interactive = matches.is_present("interactive");
+    let account = match matches.value_of("FILE") {
+        Some(path) => run_file(path, &initial, until, diff)?,
+        None if interactive => initial,
+        None => return Err("Missing required paramter file to read the budget from".to_owned())
+    };
+    let account = if interactive { repl(account)? } else { account };
+    if let Some(saved) = matches.value_of("save") {
+        err_to_str(account.save_to_file(Path::new(saved)))?;
+    }
+    if let Some(csv) = matches.value_of("csv") {
+        let mut file = err_to_str(File::create(csv))?;
+        err_to_str(account.export_csv(&mut file))?;
     }
     Ok(())
 }
@@ -0,0 +1,233 @@
+use {
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt,
+        iter::Sum,
+        ops::{Add, AddAssign, Div, Neg, Rem, Sub, SubAssign},
+        str::FromStr,
+    },
+};
+
+/// An amount of money, stored as a whole number of cents so sums are always exact
+///
+/// Deposits are split into whole cents unless the tree is given a larger unit with Action::SetUnit, like 1.00 for
+/// currencies without minor units such as JPY.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Money(i64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+    pub const CENT: Money = Money(1);
+    /// The max of an account that can grow without bound
+    pub const UNLIMITED: Money = Money(i64::MAX);
+
+    pub fn from_cents(cents: i64) -> Money {
+        Money(cents)
+    }
+
+    pub fn cents(self) -> i64 {
+        self.0
+    }
+
+    pub fn is_unlimited(self) -> bool {
+        self == Money::UNLIMITED
+    }
+
+    /// Exchange into another currency at the given rate, rounding to the nearest cent
+    pub fn convert(self, rate: f64) -> Money {
+        Money((self.0 as f64 * rate).round() as i64)
+    }
+}
+
+/// How to write amounts of money for people, like $1,234.50 or €1.234,50
+#[derive(Clone, Debug, PartialEq)]
+pub struct CurrencyFormat {
+    pub symbol: String,
+    pub decimal_sep: char,
+    pub thousands_sep: Option<char>
+}
+
+impl CurrencyFormat {
+    pub fn format_amount(&self, amount: Money) -> String {
+        if amount.is_unlimited() {
+            return "unlimited".to_owned();
+        }
+        let sign = if amount.0 < 0 { "-" } else { "" };
+        let cents = amount.0.unsigned_abs();
+        let whole = (cents / 100).to_string();
+        let mut grouped = String::new();
+        for (index, digit) in whole.chars().enumerate() {
+            if let Some(separator) = self.thousands_sep {
+                if index > 0 && (whole.len() - index).is_multiple_of(3) {
+                    grouped.push(separator);
+                }
+            }
+            grouped.push(digit);
+        }
+        format!("{}{}{}{}{:02}", sign, self.symbol, grouped, self.decimal_sep, cents % 100)
+    }
+}
+
+/// The plain format used when displaying Money, like 1234.50
+impl Default for CurrencyFormat {
+    fn default() -> CurrencyFormat {
+        CurrencyFormat { symbol: String::new(), decimal_sep: '.', thousands_sep: None }
+    }
+}
+
+// Arithmetic saturates so that anything involving an unlimited max stays unlimited
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        Money(self.0.saturating_add(other.0))
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        if self.is_unlimited() {
+            self
+        } else {
+            Money(self.0.saturating_sub(other.0))
+        }
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, other: Money) {
+        *self = *self - other;
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(self.0.saturating_neg())
+    }
+}
+
+impl Div<i64> for Money {
+    type Output = Money;
+
+    fn div(self, parts: i64) -> Money {
+        Money(self.0 / parts)
+    }
+}
+
+impl Rem<i64> for Money {
+    type Output = Money;
+
+    fn rem(self, parts: i64) -> Money {
+        Money(self.0 % parts)
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_unlimited() {
+            return write!(f, "unlimited");
+        }
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let cents = self.0.unsigned_abs();
+        write!(f, "{}{}.{:02}", sign, cents / 100, cents % 100)
+    }
+}
+
+impl fmt::Debug for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for Money {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Money, String> {
+        let error = || format!("Expected an amount of money with at most two decimal places, found {}", string);
+        let (negative, digits) = match string.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, string)
+        };
+        let mut parts = digits.splitn(2, '.');
+        let whole = parts.next().unwrap_or("");
+        let fraction = parts.next().unwrap_or("");
+        let all_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if whole.is_empty() || fraction.len() > 2 || !all_digits(whole) || !all_digits(fraction) {
+            return Err(error());
+        }
+        let whole = whole.parse::<i64>().map_err(|_| error())?;
+        let fraction = match fraction.len() {
+            0 => 0,
+            1 => fraction.parse::<i64>().map_err(|_| error())? * 10,
+            _ => fraction.parse::<i64>().map_err(|_| error())?
+        };
+        let cents = whole.checked_mul(100)
+            .and_then(|cents| cents.checked_add(fraction))
+            .ok_or_else(error)?;
+        Ok(Money(if negative { -cents } else { cents }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn displays_cents_as_a_decimal() {
+        assert_eq!(Money::from_cents(1234).to_string(), "12.34");
+        assert_eq!(Money::from_cents(5).to_string(), "0.05");
+        assert_eq!(Money::from_cents(-250).to_string(), "-2.50");
+        assert_eq!(Money::UNLIMITED.to_string(), "unlimited");
+    }
+
+    #[test]
+    fn parses_at_most_two_decimal_places() {
+        assert_eq!("12.34".parse::<Money>(), Ok(Money::from_cents(1234)));
+        assert_eq!("12.3".parse::<Money>(), Ok(Money::from_cents(1230)));
+        assert_eq!("-7".parse::<Money>(), Ok(Money::from_cents(-700)));
+        assert!("0.001".parse::<Money>().is_err());
+        assert!("1.2.3".parse::<Money>().is_err());
+    }
+
+    #[test]
+    fn sums_are_exact() {
+        let tenth = Money::from_cents(10);
+        let total: Money = (0..10).map(|_| tenth).sum();
+        assert_eq!(total, Money::from_cents(100));
+    }
+
+    #[test]
+    fn amounts_format_in_dollars() {
+        let usd = CurrencyFormat { symbol: "$".to_owned(), decimal_sep: '.', thousands_sep: Some(',') };
+        assert_eq!(usd.format_amount("1234.5".parse().unwrap()), "$1,234.50");
+        assert_eq!(usd.format_amount("1234567.89".parse().unwrap()), "$1,234,567.89");
+        assert_eq!(usd.format_amount("999".parse().unwrap()), "$999.00");
+        assert_eq!(usd.format_amount("-0.05".parse().unwrap()), "-$0.05");
+        assert_eq!(usd.format_amount(Money::UNLIMITED), "unlimited");
+    }
+
+    #[test]
+    fn amounts_format_in_the_european_style() {
+        let eur = CurrencyFormat { symbol: "€".to_owned(), decimal_sep: ',', thousands_sep: Some('.') };
+        assert_eq!(eur.format_amount("1234.5".parse().unwrap()), "€1.234,50");
+        let plain = CurrencyFormat::default();
+        assert_eq!(plain.format_amount("1234.5".parse().unwrap()), "1234.50");
+    }
+}
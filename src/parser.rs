@@ -1,28 +1,33 @@
 use crate::{
     account::{
-        Action::{self, *}, AccountType::*, Inflow::{self, *}
+        Action::{self, *}, AccountType::*, Goal, Inflow::{self, *}, Rollover
     },
-    chrono::naive::NaiveDate,
+    chrono::{naive::NaiveDate, Local},
+    money::Money,
     regex::Regex
 };
+use std::iter::{once, Peekable};
 
 pub fn parse(data: &str) -> Result<Vec<Action>, Vec<String>> {
     let (actions, errors): (Vec<_>, Vec<_>) = Regex::new("#.*\n").unwrap().replace_all(data, "\n")
         .split("\n")
         .enumerate()
         .map(|(index, line)| (index + 1, line))
-        .filter(|(_, line)| line.len() > 0)
+        .filter(|(_, line)| !line.is_empty())
         .map(|(num, line)| {
             let tokens = &mut line.split_whitespace();
             match tokens.next() {
                 Some("add") => parse_new(num, tokens),
                 Some("remove") => parse_remove(num, tokens),
+                Some("rename") => parse_rename(num, tokens),
+                Some("move") => parse_move(num, tokens),
                 Some("-") => parse_withdraw(num, tokens),
+                Some("empty") => parse_empty(num, tokens),
                 Some("+") => parse_deposit(num, tokens),
                 Some("transfer") => parse_transfer(num, tokens),
                 Some("edit") => parse_edit(num, tokens),
                 Some(other) => Err(format!("Failed to parse command at line {}: unexpected command {}", num, other)),
-                None => Err(format!("Unexpected EOF, likely an internal error"))
+                None => Err("Unexpected EOF, likely an internal error".to_owned())
             }
         })
         .partition(Result::is_ok);
@@ -33,23 +38,125 @@ pub fn parse(data: &str) -> Result<Vec<Action>, Vec<String>> {
     }
 }
 
+/// Parse a single interactive command, dated today unless it ends with `on Date`
+///
+/// The commands are `new Name Inflow (under Account)? ('branch' | 'max:' Amount)?`, `deposit Amount ('to' Account)?`,
+/// `withdraw Amount 'from' Account`, `empty Account`, and `transfer Amount 'from' Account ('to' Account)?`, with
+/// inflows written like `flex:2`. New accounts go under the root unless another parent is given.
+pub fn parse_action(line: &str) -> Result<Action, String> {
+    parse_command(line, Local::now().naive_local().date())
+}
+
+fn parse_command(line: &str, today: NaiveDate) -> Result<Action, String> {
+    // A command is always a single line
+    let num = 1;
+    let tokens = &mut line.split_whitespace().peekable();
+    match tokens.next() {
+        Some("new") => {
+            let name = next_token(num, tokens)?.to_owned();
+            let inflow = next_token(num, tokens)?.parse::<Inflow>()?;
+            let parent = parse_optional("under", num, tokens)?.unwrap_or("root").to_owned();
+            let leaf = |max| Leaf { balance: Money::ZERO, max, allow_negative: false, rollover: Rollover::Keep, goal: None, currency: None };
+            let data = match tokens.peek() {
+                Some(&"branch") => {
+                    tokens.next();
+                    Branch { children: Vec::new(), overflow: None, unit: Money::CENT }
+                }
+                Some(token) if token.starts_with("max:") => {
+                    let max = parse_money(num, &token["max:".len()..])?;
+                    tokens.next();
+                    leaf(max)
+                }
+                _ => leaf(Money::UNLIMITED)
+            };
+            let created = Some(parse_command_end(num, tokens, today)?);
+            Ok(New { name, inflow, priority: 0, parent, data, note: None, created })
+        }
+        Some("deposit") => {
+            let amount = parse_amount(num, tokens)?;
+            let account = parse_optional("to", num, tokens)?.map(str::to_owned);
+            let date = parse_command_end(num, tokens, today)?;
+            Ok(Deposit { account, amount, date })
+        }
+        Some("withdraw") => {
+            let amount = parse_amount(num, tokens)?;
+            assert_token("from", num, tokens)?;
+            let account = next_token(num, tokens)?.to_owned();
+            let date = parse_command_end(num, tokens, today)?;
+            Ok(Withdraw { account, amount, date })
+        }
+        Some("empty") => {
+            let account = next_token(num, tokens)?.to_owned();
+            let date = parse_command_end(num, tokens, today)?;
+            Ok(Empty { account, date })
+        }
+        Some("transfer") => {
+            let amount = parse_amount(num, tokens)?;
+            assert_token("from", num, tokens)?;
+            let from = next_token(num, tokens)?.to_owned();
+            let to = parse_optional("to", num, tokens)?.map(str::to_owned);
+            let date = parse_command_end(num, tokens, today)?;
+            Ok(Transfer { from, to, amount, date })
+        }
+        Some(other) => Err(format!("Unknown command {}, expected new, deposit, withdraw, empty, or transfer", other)),
+        None => Err("Expected a command".to_owned())
+    }
+}
+
+// Read the optional 'on Date' that ends a command
+fn parse_command_end<'a, 'b>(num: usize, line: &'a mut Peekable<impl Iterator<Item = &'b str>>, today: NaiveDate) -> Result<NaiveDate, String> {
+    let date = match parse_optional("on", num, line)? {
+        Some(date) => parse_date(num, &mut once(date))?,
+        None => today
+    };
+    match line.next() {
+        Some(other) => Err(format!("Expected either 'on date' or the end of the command, found {}", other)),
+        None => Ok(date)
+    }
+}
+
+// Read the token after the keyword if the keyword comes next
+fn parse_optional<'a, 'b>(keyword: &str, num: usize, line: &'a mut Peekable<impl Iterator<Item = &'b str>>) -> Result<Option<&'b str>, String> {
+    if line.peek() == Some(&keyword) {
+        line.next();
+        next_token(num, line).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
 fn parse_new<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<Action, String> {
     let parent = next_token(num, line)?.to_owned();
     assert_token(">", num, line)?;
     let name = next_token(num, line)?.to_owned();
     let inflow = parse_inflow(num, line)?;
-    let data = match line.next() {
-        Some("with") => {
-            let balance = parse_amount(num, line)?;
-            let max = parse_max(num, line)?;
-            Leaf { balance, max }
-        }
-        Some(other) => {
-            Err(format!("Unexpected token {} at line {}", other, num))?
+    let mut data = Branch { children: Vec::new(), overflow: None, unit: Money::CENT };
+    let mut priority = 0;
+    let mut created = None;
+    let mut note = None;
+    while let Some(token) = line.next() {
+        match (token, &mut data) {
+            ("with", Branch { .. }) => {
+                let balance = parse_amount(num, line)?;
+                data = Leaf { balance, max: Money::UNLIMITED, allow_negative: false, rollover: Rollover::Keep, goal: None, currency: None };
+            }
+            ("max", Leaf { max, .. }) => *max = parse_amount(num, line)?,
+            ("credit", Leaf { allow_negative, .. }) => *allow_negative = true,
+            ("reset", Leaf { rollover, .. }) => *rollover = Rollover::Reset,
+            ("currency", Leaf { currency, .. }) => *currency = Some(next_token(num, line)?.to_owned()),
+            ("goal", Leaf { goal, .. }) => {
+                let target = parse_amount(num, line)?;
+                assert_token("by", num, line)?;
+                *goal = Some(Goal { target, by: parse_date(num, line)? });
+            }
+            ("priority", _) => priority = parse_priority(num, line)?,
+            ("on", _) => created = Some(parse_date(num, line)?),
+            // The note takes up the rest of the line
+            ("note", _) => note = Some(line.by_ref().collect::<Vec<_>>().join(" ")),
+            (other, _) => return Err(format!("Unexpected token {} at line {}", other, num))
         }
-        None => Branch { children: Vec::new() }
-    };
-    Ok(New { name, parent, inflow, data })
+    }
+    Ok(New { name, inflow, priority, parent, data, note, created })
 }
 
 fn parse_remove<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<Action, String> {
@@ -57,6 +164,19 @@ fn parse_remove<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>)
     Ok(Remove { name })
 }
 
+fn parse_rename<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<Action, String> {
+    let name = next_token(num, line)?.to_owned();
+    let new_name = next_token(num, line)?.to_owned();
+    Ok(Rename { name, new_name })
+}
+
+fn parse_move<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<Action, String> {
+    let name = next_token(num, line)?.to_owned();
+    assert_token("to", num, line)?;
+    let new_parent = next_token(num, line)?.to_owned();
+    Ok(Move { name, new_parent })
+}
+
 fn parse_edit<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<Action, String> {
     let name = next_token(num, line)?.to_owned();
     let inflow = parse_inflow(num, line)?;
@@ -64,11 +184,11 @@ fn parse_edit<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -
     Ok(Edit { name, inflow, max })
 }
 
-fn parse_max<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<f64, String> {
+fn parse_max<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<Money, String> {
     match line.next() {
         Some("max") => Ok(parse_amount(num, line)?),
         Some(other) => Err(format!("Expected either 'max value' or end-of-line, found {} at line {}", other, num)),
-        None => Ok(std::f64::INFINITY),
+        None => Ok(Money::UNLIMITED),
     }
 }
 
@@ -81,6 +201,13 @@ fn parse_withdraw<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str
     Ok(Withdraw { account, amount, date })
 }
 
+fn parse_empty<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<Action, String> {
+    let account = next_token(num, line)?.to_owned();
+    assert_token("on", num, line)?;
+    let date = parse_date(num, line)?;
+    Ok(Empty { account, date })
+}
+
 fn parse_deposit<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<Action, String> {
     let amount = parse_amount(num, line)?;
     let (account, date) = match next_token(num, line)? {
@@ -104,8 +231,24 @@ fn parse_transfer<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str
     let (to, date) = match next_token(num, line)? {
         "to" => {
             let account = next_token(num, line)?.to_owned();
-            assert_token("on", num, line)?;
-            (Some(account), parse_date(num, line)?)
+            match next_token(num, line)? {
+                "under" => {
+                    let parent = next_token(num, line)?.to_owned();
+                    assert_token("on", num, line)?;
+                    let date = parse_date(num, line)?;
+                    return Ok(TransferToNew { from, to: account, parent, amount, date });
+                }
+                "at" => {
+                    let rate = next_token(num, line)?;
+                    let rate = rate.parse::<f64>()
+                        .map_err(|_| format!("Expected floating point literal at line {}, found {}", num, rate))?;
+                    assert_token("on", num, line)?;
+                    let date = parse_date(num, line)?;
+                    return Ok(TransferFx { from, to: account, amount, rate, date });
+                }
+                "on" => (Some(account), parse_date(num, line)?),
+                other => return Err(format!("Expected 'under', 'at', or 'on', found {} at line {}", other, num)),
+            }
         }
         "on" => {
             (None, parse_date(num, line)?)
@@ -116,18 +259,29 @@ fn parse_transfer<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str
 }
 
 fn parse_inflow<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<Inflow, String> {
-    let amount = parse_amount(num, line)?;
-    let inflow = match next_token(num, line)? {
-        "flex" => Ok(Flex(amount)),
-        "fixed" => Ok(Fixed(amount)),
-        other => Err(format!("Expected either 'flex' or 'fixed', found {} at line {}", other, num))
-    };
-    Ok(inflow?)
+    let amount = next_token(num, line)?;
+    if amount == "fill" {
+        return Ok(FillToMax);
+    }
+    match next_token(num, line)? {
+        "flex" => amount.parse::<f64>()
+            .map(Flex)
+            .map_err(|_| format!("Expected floating point literal at line {}, found {}", num, amount)),
+        "fixed" => parse_money(num, amount).map(Fixed),
+        "percent" => amount.parse::<f64>()
+            .map(Percent)
+            .map_err(|_| format!("Expected floating point literal at line {}, found {}", num, amount)),
+        other => Err(format!("Expected 'flex', 'fixed', or 'percent', found {} at line {}", other, num))
+    }
 }
 
-fn parse_amount<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<f64, String> {
+fn parse_amount<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<Money, String> {
     let token = next_token(num, line)?;
-    token.parse::<f64>().map_err(|_| format!("Expected floating point literal at line {}, found {}", num, token))
+    parse_money(num, token)
+}
+
+fn parse_money(num: usize, token: &str) -> Result<Money, String> {
+    token.parse::<Money>().map_err(|e| format!("{} at line {}", e, num))
 }
 
 pub fn parse_date<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<NaiveDate, String> {
@@ -135,7 +289,14 @@ pub fn parse_date<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str
     let month = parse_int(num, date)?;
     let day = parse_int(num, date)?;
     let year = parse_int(num, date)?;
-    Ok(NaiveDate::from_ymd(year as i32, month, day))
+    NaiveDate::from_ymd_opt(year as i32, month, day)
+        .ok_or_else(|| format!("There is no date {}/{}/{} at line {}", month, day, year, num))
+}
+
+fn parse_priority<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<i32, String> {
+    next_token(num, line)?
+        .parse::<i32>()
+        .map_err(|_| format!("Failed to read a priority at line {}", num))
 }
 
 fn parse_int<'a, 'b>(num: usize, line: &'a mut impl Iterator<Item = &'b str>) -> Result<u32, String> {
@@ -154,3 +315,40 @@ fn assert_token<'a, 'b>(expected: &str, num: usize, line: &'a mut impl Iterator<
         other => Err(format!("Expected token {} at line {}, found {}", expected, num, other))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn commands_parse_into_actions() {
+        let today = date(2020, 3, 1);
+        let food = Leaf { balance: Money::ZERO, max: "200".parse().unwrap(), allow_negative: false, rollover: Rollover::Keep, goal: None, currency: None };
+        assert_eq!(parse_command("new Food flex:2 under Living max:200", today), Ok(New {
+            name: "Food".to_owned(), inflow: Flex(2.0), priority: 0, parent: "Living".to_owned(), data: food, note: None, created: Some(today)
+        }));
+        assert_eq!(parse_command("new Living fixed:50 branch", today), Ok(New {
+            name: "Living".to_owned(), inflow: Fixed("50".parse().unwrap()), priority: 0, parent: "root".to_owned(),
+            data: Branch { children: Vec::new(), overflow: None, unit: Money::CENT }, note: None, created: Some(today)
+        }));
+        assert_eq!(parse_command("deposit 1000", today), Ok(Deposit { account: None, amount: "1000".parse().unwrap(), date: today }));
+        assert_eq!(parse_command("withdraw 50 from Food on 2/14/2020", today),
+            Ok(Withdraw { account: "Food".to_owned(), amount: "50".parse().unwrap(), date: date(2020, 2, 14) }));
+        assert_eq!(parse_command("transfer 25 from Food to Fun", today),
+            Ok(Transfer { from: "Food".to_owned(), to: Some("Fun".to_owned()), amount: "25".parse().unwrap(), date: today }));
+    }
+
+    #[test]
+    fn bad_commands_are_errors() {
+        let today = date(2020, 3, 1);
+        assert_eq!(parse_command("spend 5", today), Err("Unknown command spend, expected new, deposit, withdraw, empty, or transfer".to_owned()));
+        assert_eq!(parse_command("withdraw 50 Food", today), Err("Expected token from at line 1, found Food".to_owned()));
+        assert_eq!(parse_command("deposit 5 tomorrow", today), Err("Expected either 'on date' or the end of the command, found tomorrow".to_owned()));
+        assert!(parse_command("new Food", today).is_err());
+        assert_eq!(parse_action("deposit 5 on 13/01/2020"), Err("There is no date 13/1/2020 at line 1".to_owned()));
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    env, fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+// Run the interactive mode with the given commands on stdin, returning what it printed
+fn run_commands(commands: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_budgeting"))
+        .arg("--interactive")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(commands.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// Run the budget file with the given contents and extra arguments, returning what it printed
+fn run_file(name: &str, contents: &str, args: &[&str]) -> String {
+    let path = env::temp_dir().join(name);
+    fs::write(&path, contents).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_budgeting")).arg(&path).args(args).output().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn until_leaves_out_every_later_action() {
+    let output = run_file("budgeting-until.budget", "add root > Food 1 flex with 0
++ 100 to Food on 01/01/2020
+empty Food on 02/01/2020
+transfer 10 from Food to New under root on 02/01/2020
+", &["--until", "01/15/2020"]);
+    assert_eq!(output, "The budget as of 2020-01-15:\nFlex(1.0):\troot: 100.00\nFlex(1.0):\t  Food: 100.00\n\n");
+}
+
+#[test]
+fn commands_are_applied_in_order() {
+    let output = run_commands("new Food flex:1 max:200 on 1/1/2020
+new Fun flex:1 on 1/1/2020
+deposit 100
+withdraw 30 from Food
+transfer 10 from Fun to Food
+quit
+deposit 1000
+");
+    let last = output.rsplit("> ").nth(1).unwrap();
+    assert_eq!(last, "Flex(1.0):\troot: 70.00\nFlex(1.0):\t  Food: 30.00 (since 2020-01-01)\nFlex(1.0):\t  Fun: 40.00 (since 2020-01-01)\n\n");
+}
+
+#[test]
+fn errors_are_printed_without_stopping() {
+    let output = run_commands("new Food flex:1 on 1/1/2020
+deposit 5 on 13/01/2020
+withdraw 5 from Fun
+deposit 20
+");
+    assert!(output.contains("There is no date 13/1/2020 at line 1"));
+    assert!(output.contains("Could not find parent account Fun to withdraw from"));
+    assert!(output.ends_with("Flex(1.0):\troot: 20.00\nFlex(1.0):\t  Food: 20.00 (since 2020-01-01)\n\n> "));
+}